@@ -1,131 +1,720 @@
-use std::io::Write;
-use crate::{print_str, print_buf, read_buf, KeyCode, Pos};
-
-pub(crate) fn read_key() -> KeyCode {
-    let keycode = loop {
-        let mut buffer = [0u8; 8];
-        let _ = read_buf(&mut buffer);
-
-        if buffer[0] == 0x1b && buffer[1] == b'[' {
-            let next = if buffer[2] == 0x31 && buffer[3] == 0x3b {
-                5
-            } else {
-                2
-            };
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use crate::{print_buf, read_buf, wait_input, resize_take, window_size_tracked};
+use crate::{Attributes, ClearType, Color, ColorDepth, CursorShape, Error, Event, KeyEvent, MouseMode, Pos, Response, Result};
+use crate::parser::Parser;
+use crate::color;
 
-            match buffer[next] {
-                65 => break KeyCode::ArrowUp,
-                66 => break KeyCode::ArrowDown,
-                67 => break KeyCode::ArrowRight,
-                68 => break KeyCode::ArrowLeft,
-                _ => {}
-            }
+// NOTE: Shared between calls so that input which arrives together with a key, or a sequence
+//       split across reads, is not lost.
+static PARSER: Mutex<Parser> = Mutex::new(Parser::new());
+
+// NOTE: How long to wait for the rest of a sequence after an ESC before treating it as
+//       the Escape key itself.
+const ESCAPE_TIMEOUT: Duration = Duration::from_millis(50);
+
+// NOTE: Set while the alternate screen is shown, so that it can be left again on panic.
+static ALTERNATE_SCREEN: AtomicBool = AtomicBool::new(false);
+
+// NOTE: Set while the cursor has a shape other than the default one, so that it can be reset
+//       when the terminal gets restored.
+static CURSOR_SHAPE: AtomicBool = AtomicBool::new(false);
+
+// NOTE: How long to wait for the terminal to answer a query.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// What ended a `wait_input` call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Wake {
+    /// Input is ready to be read without blocking.
+    Input,
+    /// The terminal was resized, collected with `resize_take`.
+    Resize,
+    Timeout,
+}
+
+/// Terminal the readers and queries talk to.
+pub(crate) trait Device {
+    /// Waits until input is available, the terminal gets resized or `timeout` runs out.
+    fn input_wait(&self, timeout: Option<Duration>) -> Wake;
+    fn input_read(&self, buffer: &mut [u8]) -> Result<usize>;
+    fn output_write(&self, buffer: &[u8]) -> Result<()>;
+    /// Size reported with `Event::Resize`.
+    // NOTE: Must not ask the terminal, the reader calling it holds the parser.
+    fn size(&self) -> Result<Pos>;
+}
+
+/// The terminal of the process, used together with `PARSER`.
+struct Stdio;
+
+impl Device for Stdio {
+    fn input_wait(&self, timeout: Option<Duration>) -> Wake {
+        return wait_input(timeout);
+    }
+
+    fn input_read(&self, buffer: &mut [u8]) -> Result<usize> {
+        return read_buf(buffer);
+    }
+
+    fn output_write(&self, buffer: &[u8]) -> Result<()> {
+        print_buf(buffer, buffer.len())?;
+        return Ok(());
+    }
+
+    fn size(&self) -> Result<Pos> {
+        return Ok(window_size_tracked()?.size);
+    }
+}
+
+/// Reads the next event, waiting for at most `timeout` or indefinitely when it is `None`.
+pub(crate) fn read_event(timeout: Option<Duration>) -> Result<Option<Event>> {
+    let mut parser = PARSER.lock().unwrap_or_else(|error| error.into_inner());
+    return device_read_event(&Stdio, &mut parser, timeout);
+}
+
+/// Same as `read_event`, for any terminal with its own parser.
+pub(crate) fn device_read_event(device: &impl Device, parser: &mut Parser, timeout: Option<Duration>) -> Result<Option<Event>> {
+    let start = Instant::now();
+
+    let event = loop {
+        if let Some(event) = parser.next() {
+            break event;
         }
 
-        let data = buffer[0] as u8;
-        match data {
-            b'0'..=b'9' => break KeyCode::Char(char::from_u32(data as u32).unwrap()),
-            b'a'..=b'z' => break KeyCode::Char(char::from_u32(data as u32).unwrap()),
-            b'A'..=b'Z' => break KeyCode::Char(char::from_u32(data as u32).unwrap()),
-            b'\n'  => break KeyCode::Enter,
-            b'\r'  => break KeyCode::Enter,
-            b' '   => break KeyCode::Space,
-            127 => break KeyCode::Backspace,
-            _   => {}
-            // _   => break KeyCode::Other(u64::from_ne_bytes(buffer)),
+        if resize_take() {
+            break Event::Resize(device.size()?);
         }
+
+        let wake = if parser.escape_pending() {
+            match device.input_wait(Some(ESCAPE_TIMEOUT)) {
+                Wake::Timeout => {
+                    parser.idle();
+                    continue;
+                }
+                wake => wake,
+            }
+        } else {
+            let remaining = timeout.map(|timeout| timeout.saturating_sub(start.elapsed()));
+            device.input_wait(remaining)
+        };
+
+        match wake {
+            Wake::Input   => {}
+            Wake::Resize  => continue,
+            Wake::Timeout => return Ok(None),
+        }
+
+        input_read(device, parser)?;
     };
 
-    return keycode;
+    return Ok(Some(event));
+}
+
+/// Same as `read_event`, skipping over everything that is not a key.
+pub(crate) fn read_key_event(timeout: Option<Duration>) -> Result<Option<KeyEvent>> {
+    let mut parser = PARSER.lock().unwrap_or_else(|error| error.into_inner());
+    return device_read_key_event(&Stdio, &mut parser, timeout);
 }
 
-pub(crate) fn cursor_set(x: u16, y: u16) {
+pub(crate) fn device_read_key_event(device: &impl Device, parser: &mut Parser, timeout: Option<Duration>) -> Result<Option<KeyEvent>> {
+    let start = Instant::now();
+    loop {
+        let remaining = timeout.map(|timeout| timeout.saturating_sub(start.elapsed()));
+        match device_read_event(device, parser, remaining)? {
+            Some(Event::Key(key)) => return Ok(Some(key)),
+            Some(_) => continue,
+            None => return Ok(None),
+        }
+    }
+}
+
+/// Writes a sequence into a scratch buffer with `write` and prints it in one go.
+pub(crate) fn print_with(write: impl FnOnce(&mut Vec<u8>) -> Result<()>) -> Result<()> {
+    return device_print_with(&Stdio, write);
+}
+
+pub(crate) fn device_print_with(device: &impl Device, write: impl FnOnce(&mut Vec<u8>) -> Result<()>) -> Result<()> {
+    let mut buffer = Vec::with_capacity(32);
+    write(&mut buffer)?;
+    device.output_write(&buffer)?;
+    return Ok(());
+}
+
+pub(crate) fn cursor_set(out: &mut impl Write, x: u16, y: u16) -> Result<()> {
     // ANSI console cursor position is 0 and NOT 1 indexed.
     let ansi_x = x.saturating_add(1);
     let ansi_y = y.saturating_add(1);
 
-    let mut buffer = [0u8; 16];
-    write!(&mut buffer[..], "\x1b[{ansi_y};{ansi_x}H").unwrap();
-    print_buf(&buffer, buffer.len());
+    write!(out, "\x1b[{ansi_y};{ansi_x}H")?;
+    return Ok(());
+}
+
+pub(crate) fn cursor_up(out: &mut impl Write, count: u16) -> Result<()> {
+    // NOTE: A count of 0 moves by one as well, so it has to be skipped.
+    if count == 0 {
+        return Ok(());
+    }
+
+    write!(out, "\x1b[{count}A")?;
+    return Ok(());
 }
 
-pub(crate) fn parse_pos(buffer: &[u8]) -> Pos {
-    let mut i = 0;
-    while i < buffer.len() {
-        let byte = buffer[i];
-        i += 1;
+pub(crate) fn cursor_down(out: &mut impl Write, count: u16) -> Result<()> {
+    if count == 0 {
+        return Ok(());
+    }
 
-        if byte == 0 || byte == b'[' {
-            break;
+    write!(out, "\x1b[{count}B")?;
+    return Ok(());
+}
+
+pub(crate) fn cursor_right(out: &mut impl Write, count: u16) -> Result<()> {
+    if count == 0 {
+        return Ok(());
+    }
+
+    write!(out, "\x1b[{count}C")?;
+    return Ok(());
+}
+
+pub(crate) fn cursor_left(out: &mut impl Write, count: u16) -> Result<()> {
+    if count == 0 {
+        return Ok(());
+    }
+
+    write!(out, "\x1b[{count}D")?;
+    return Ok(());
+}
+
+pub(crate) fn cursor_line_next(out: &mut impl Write, count: u16) -> Result<()> {
+    // NOTE: A count of 0 moves by one as well, so it has to be skipped.
+    if count == 0 {
+        return Ok(());
+    }
+
+    write!(out, "\x1b[{count}E")?;
+    return Ok(());
+}
+
+pub(crate) fn cursor_line_prev(out: &mut impl Write, count: u16) -> Result<()> {
+    // NOTE: A count of 0 moves by one as well, so it has to be skipped.
+    if count == 0 {
+        return Ok(());
+    }
+
+    write!(out, "\x1b[{count}F")?;
+    return Ok(());
+}
+
+pub(crate) fn cursor_column(out: &mut impl Write, x: u16) -> Result<()> {
+    let ansi_x = x.saturating_add(1);
+    write!(out, "\x1b[{ansi_x}G")?;
+    return Ok(());
+}
+
+pub(crate) fn cursor_save(out: &mut impl Write) -> Result<()> {
+    // NOTE: Both the DEC and the SCO variant, terminals differ in which one they know.
+    let ansi_save = "\x1b7\x1b[s";
+    out.write_all(ansi_save.as_bytes())?;
+    return Ok(());
+}
+
+pub(crate) fn cursor_restore(out: &mut impl Write) -> Result<()> {
+    let ansi_restore = "\x1b8\x1b[u";
+    out.write_all(ansi_restore.as_bytes())?;
+    return Ok(());
+}
+
+pub(crate) fn cursor_hide(out: &mut impl Write) -> Result<()> {
+    let ansi_hide = "\x1b[?25l";
+    out.write_all(ansi_hide.as_bytes())?;
+    return Ok(());
+}
+
+pub(crate) fn cursor_show(out: &mut impl Write) -> Result<()> {
+    let ansi_show = "\x1b[?25h";
+    out.write_all(ansi_show.as_bytes())?;
+    return Ok(());
+}
+
+pub(crate) fn cursor_shape(out: &mut impl Write, shape: CursorShape) -> Result<()> {
+    cursor_shape_write(out, shape)?;
+    CURSOR_SHAPE.store(shape != CursorShape::Default, Ordering::Relaxed);
+    return Ok(());
+}
+
+/// Writes the sequence for `shape` without remembering to reset it later.
+pub(crate) fn cursor_shape_write(out: &mut impl Write, shape: CursorShape) -> Result<()> {
+    let ansi_shape = match shape {
+        CursorShape::Default           => "\x1b[0 q",
+        CursorShape::BlinkingBlock     => "\x1b[1 q",
+        CursorShape::SteadyBlock       => "\x1b[2 q",
+        CursorShape::BlinkingUnderline => "\x1b[3 q",
+        CursorShape::SteadyUnderline   => "\x1b[4 q",
+        CursorShape::BlinkingBar       => "\x1b[5 q",
+        CursorShape::SteadyBar         => "\x1b[6 q",
+    };
+
+    out.write_all(ansi_shape.as_bytes())?;
+    return Ok(());
+}
+
+/// Resets the cursor shape if it was changed, used when the terminal gets restored.
+pub(crate) fn cursor_shape_restore() {
+    if CURSOR_SHAPE.load(Ordering::Relaxed) {
+        let _ = print_with(|out| cursor_shape(out, CursorShape::Default));
+    }
+}
+
+pub(crate) fn cursor_get() -> Result<Pos> {
+    let mut parser = PARSER.lock().unwrap_or_else(|error| error.into_inner());
+    return device_cursor_get(&Stdio, &mut parser);
+}
+
+pub(crate) fn device_cursor_get(device: &impl Device, parser: &mut Parser) -> Result<Pos> {
+    parser.cursor_expect(true);
+
+    let ansi_cursor_get = "\x1b[6n";
+    let result = device.output_write(ansi_cursor_get.as_bytes()).and_then(|_| {
+        return response_read(device, parser, |response| match response {
+            Response::CursorPosition(_) => return true,
+            // A report the parser could not make sense of.
+            Response::Other(sequence) => return sequence.last() == Some(&b'R'),
+            _ => return false,
+        });
+    });
+
+    // A report which never arrived must not turn later F3 presses into reports.
+    if result.is_err() {
+        parser.cursor_expect(false);
+    }
+
+    match result? {
+        Response::CursorPosition(pos) => return Ok(pos),
+        _ => return Err(Error::MalformedResponse),
+    }
+}
+
+/// Waits for the reply to a query, taking the first response accepted by `matches`.
+/// Everything else arriving in the meantime stays queued for `read_event`.
+fn response_read(device: &impl Device, parser: &mut Parser, matches: impl Fn(&Response) -> bool) -> Result<Response> {
+    let start = Instant::now();
+    loop {
+        if let Some(response) = parser.response_take(&matches) {
+            return Ok(response);
         }
+
+        let remaining = RESPONSE_TIMEOUT.saturating_sub(start.elapsed());
+        match device.input_wait(Some(remaining)) {
+            Wake::Input   => {}
+            Wake::Resize  => continue,
+            Wake::Timeout => return Err(Error::Timeout),
+        }
+
+        input_read(device, parser)?;
     }
+}
 
-    let mut y = 0u16;
-    while i < buffer.len() {
-        let byte = buffer[i];
-        i += 1;
+/// Reads whatever input is available and feeds it to the parser.
+fn input_read(device: &impl Device, parser: &mut Parser) -> Result<()> {
+    let mut buffer = [0u8; 64];
+    let bytes_read = device.input_read(&mut buffer)?;
+    if bytes_read == 0 {
+        return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+    }
+
+    parser.advance(&buffer[..bytes_read]);
+    return Ok(());
+}
 
-        match byte {
-            b'0'..=b'9' => {
-                y *= 10;
-                y += (byte - b'0') as u16;
+pub(crate) fn console_clear(out: &mut impl Write) -> Result<()> {
+    let ansi_move = "\x1b[1;1H";
+    out.write_all(ansi_move.as_bytes())?;
+
+    let ansi_clear = "\x1b[0J";
+    out.write_all(ansi_clear.as_bytes())?;
+    return Ok(());
+}
+
+pub(crate) fn console_erase(out: &mut impl Write, clear_type: ClearType) -> Result<()> {
+    let ansi_erase = match clear_type {
+        ClearType::LineEnd     => "\x1b[0K",
+        ClearType::LineStart   => "\x1b[1K",
+        ClearType::Line        => "\x1b[2K",
+        ClearType::ScreenEnd   => "\x1b[0J",
+        ClearType::ScreenStart => "\x1b[1J",
+        ClearType::Screen      => "\x1b[2J",
+        ClearType::Scrollback  => "\x1b[3J",
+        // NOTE: A count of 0 erases one cell as well, so it has to be skipped.
+        ClearType::Characters(0) => return Ok(()),
+        ClearType::Characters(count) => {
+            write!(out, "\x1b[{count}X")?;
+            return Ok(());
+        }
+    };
+
+    out.write_all(ansi_erase.as_bytes())?;
+    return Ok(());
+}
+
+pub(crate) fn color_reset(out: &mut impl Write, styled: bool) -> Result<()> {
+    if !styled {
+        return Ok(());
+    }
+
+    let ansi_reset = "\x1b[0m";
+    out.write_all(ansi_reset.as_bytes())?;
+    return Ok(());
+}
+
+pub(crate) fn color_bg(out: &mut impl Write, styled: bool, red: u8, green: u8, blue: u8) -> Result<()> {
+    return color_print(out, styled, Color::Rgb { red, green, blue }, 40);
+}
+
+pub(crate) fn color_fg(out: &mut impl Write, styled: bool, red: u8, green: u8, blue: u8) -> Result<()> {
+    return color_print(out, styled, Color::Rgb { red, green, blue }, 30);
+}
+
+pub(crate) fn color_underline(out: &mut impl Write, styled: bool, red: u8, green: u8, blue: u8) -> Result<()> {
+    return color_print(out, styled, Color::Rgb { red, green, blue }, 50);
+}
+
+pub(crate) fn color_fg_set(out: &mut impl Write, styled: bool, color: Color) -> Result<()> {
+    return color_print(out, styled, color, 30);
+}
+
+pub(crate) fn color_bg_set(out: &mut impl Write, styled: bool, color: Color) -> Result<()> {
+    return color_print(out, styled, color, 40);
+}
+
+pub(crate) fn color_underline_set(out: &mut impl Write, styled: bool, color: Color) -> Result<()> {
+    return color_print(out, styled, color, 50);
+}
+
+/// Prints `color` downsampled to the colour depth of the terminal, unless styling is off.
+fn color_print(out: &mut impl Write, styled: bool, color: Color, base: u8) -> Result<()> {
+    if !styled {
+        return Ok(());
+    }
+
+    let Some(color) = color::downsample(color, color::depth()) else {
+        return Ok(());
+    };
+
+    return color_write(out, color, base);
+}
+
+/// Writes the SGR sequence for `color` as is, with `base` being 30 for text, 40 for the
+/// background and 50 for underlines.
+pub(crate) fn color_write(out: &mut impl Write, color: Color, base: u8) -> Result<()> {
+    let extended = base + 8;
+    match color {
+        Color::Default => {
+            let code = base + 9;
+            write!(out, "\x1b[{code}m")?;
+            return Ok(());
+        }
+        Color::Indexed(index) => {
+            write!(out, "\x1b[{extended};5;{index}m")?;
+            return Ok(());
+        }
+        Color::Rgb { red, green, blue } => {
+            write!(out, "\x1b[{extended};2;{red};{green};{blue}m")?;
+            return Ok(());
+        }
+        named => {
+            let index = named.index().unwrap_or_default();
+
+            // NOTE: Underlines have no short codes for the named colours.
+            if base == 50 {
+                write!(out, "\x1b[{extended};5;{index}m")?;
+                return Ok(());
             }
-            _ => break,
+
+            // Bright colours are 90 to 97 for text and 100 to 107 for the background.
+            let code = match index {
+                0..=7 => base + index,
+                _     => base + 60 + index - 8,
+            };
+            write!(out, "\x1b[{code}m")?;
+            return Ok(());
+        }
+    }
+}
+
+/// Asks the terminal whether it shows 24-bit colours, raising the colour depth if it does.
+// NOTE: Sets a 24-bit background and reads it back with DECRQSS, terminals without 24-bit
+//       colours report a palette colour instead. Every terminal answers the device attributes
+//       query sent after it, so an unanswered DECRQSS does not have to run into the timeout.
+pub(crate) fn color_depth_query() -> Result<ColorDepth> {
+    let mut parser = PARSER.lock().unwrap_or_else(|error| error.into_inner());
+    return device_color_depth_query(&Stdio, &mut parser);
+}
+
+pub(crate) fn device_color_depth_query(device: &impl Device, parser: &mut Parser) -> Result<ColorDepth> {
+    parser.device_control_expect(true);
+
+    let ansi_query = "\x1b[48;2;1;2;3m\x1bP$qm\x1b\\\x1b[49m\x1b[c";
+    let result = device.output_write(ansi_query.as_bytes()).and_then(|_| {
+        return response_read(device, parser, |response| match response {
+            Response::Other(sequence) => sequence.first() == Some(&b'?') && sequence.last() == Some(&b'c'),
+            _ => false,
+        });
+    });
+
+    parser.device_control_expect(false);
+    result?;
+
+    let reply = parser.response_take(|response| matches!(response, Response::DeviceControl(_)));
+    if let Some(Response::DeviceControl(data)) = reply {
+        // Valid replies start with `1 $ r`, and list the colour with either separator.
+        let data = String::from_utf8_lossy(&data);
+        let true_color = data.starts_with("1$r") && (data.contains("1:2:3") || data.contains("1;2;3"));
+
+        // Colours turned off on purpose (NO_COLOR, TERM=dumb) stay off.
+        if true_color && color::depth() != ColorDepth::None {
+            color::depth_set(ColorDepth::TrueColor);
         }
     }
 
-    let mut x = 0u16;
-    while i < buffer.len() {
-        let byte = buffer[i];
-        i += 1;
+    return Ok(color::depth());
+}
+
+pub(crate) fn attributes_set(out: &mut impl Write, styled: bool, attributes: Attributes) -> Result<()> {
+    // NOTE: Only one underline style can be active, the most distinct one given wins.
+    let codes = [
+        (Attributes::BOLD,             "1"),
+        (Attributes::DIM,              "2"),
+        (Attributes::ITALIC,           "3"),
+        (Attributes::CURLY_UNDERLINE,  "4:3"),
+        (Attributes::DOUBLE_UNDERLINE, "4:2"),
+        (Attributes::DOTTED_UNDERLINE, "4:4"),
+        (Attributes::DASHED_UNDERLINE, "4:5"),
+        (Attributes::UNDERLINE,        "4"),
+        (Attributes::BLINK,            "5"),
+        (Attributes::REVERSE,          "7"),
+        (Attributes::HIDDEN,           "8"),
+        (Attributes::STRIKETHROUGH,    "9"),
+    ];
 
-        match byte {
-            b'0'..=b'9' => {
-                x *= 10;
-                x += (byte - b'0') as u16;
+    let mut underline = false;
+    let mut sequence = String::from("\x1b[");
+    for (attribute, code) in codes {
+        if !attributes.contains(attribute) {
+            continue;
+        }
+
+        if Attributes::UNDERLINES.contains(attribute) {
+            if underline {
+                continue;
             }
-            _ => break,
+
+            underline = true;
+        }
+
+        if sequence.len() > 2 {
+            sequence.push(';');
         }
+
+        sequence.push_str(code);
     }
 
-    return Pos { x, y }
+    return sgr_print(out, styled, sequence);
 }
 
-pub(crate) fn cursor_get() -> Pos {
-    let ansi_cursor_get = "\x1b[6n";
-    print_str(ansi_cursor_get);
+/// Turns off the given attributes, leaving colours and all other attributes alone.
+// NOTE: Bold and dim share their reset code, clearing one of them clears the other as well.
+pub(crate) fn attributes_clear(out: &mut impl Write, styled: bool, attributes: Attributes) -> Result<()> {
+    let codes = [
+        (Attributes::BOLD | Attributes::DIM, "22"),
+        (Attributes::ITALIC,                 "23"),
+        (Attributes::UNDERLINES,             "24"),
+        (Attributes::BLINK,                  "25"),
+        (Attributes::REVERSE,                "27"),
+        (Attributes::HIDDEN,                 "28"),
+        (Attributes::STRIKETHROUGH,          "29"),
+    ];
 
-    let mut buffer = [0u8; 16];
-    let _ = read_buf(&mut buffer);
+    let mut sequence = String::from("\x1b[");
+    for (attribute, code) in codes {
+        if !attributes.intersects(attribute) {
+            continue;
+        }
+
+        if sequence.len() > 2 {
+            sequence.push(';');
+        }
 
-    let mut pos = parse_pos(&buffer);
-    pos.x = pos.x.saturating_sub(1);
-    pos.y = pos.y.saturating_sub(1);
+        sequence.push_str(code);
+    }
 
-    return pos;
+    return sgr_print(out, styled, sequence);
 }
 
-pub(crate) fn console_clear() {
-    let ansi_move = "\x1b[1;1H";
-    print_str(ansi_move);
+/// Finishes and prints an SGR sequence started with `ESC [`, unless no codes were added.
+// NOTE: An empty `ESC [ m` would reset everything.
+fn sgr_print(out: &mut impl Write, styled: bool, mut sequence: String) -> Result<()> {
+    if sequence.len() == 2 || !styled {
+        return Ok(());
+    }
 
-    let ansi_clear = "\x1b[0J";
-    print_str(ansi_clear);
+    sequence.push('m');
+    out.write_all(sequence.as_bytes())?;
+    return Ok(());
 }
 
-pub(crate) fn color_reset() {
-    let ansi_reset = "\x1b[0m";
-    print_str(ansi_reset);
+pub(crate) fn mouse_enable(out: &mut impl Write, mode: MouseMode) -> Result<()> {
+    let ansi_mode = match mode {
+        MouseMode::Click  => "\x1b[?1000h",
+        MouseMode::Drag   => "\x1b[?1002h",
+        MouseMode::Motion => "\x1b[?1003h",
+    };
+    out.write_all(ansi_mode.as_bytes())?;
+
+    // NOTE: Terminals without SGR support ignore this and fall back to X10 style reports.
+    let ansi_sgr = "\x1b[?1006h";
+    out.write_all(ansi_sgr.as_bytes())?;
+    return Ok(());
+}
+
+pub(crate) fn mouse_disable(out: &mut impl Write) -> Result<()> {
+    let ansi_disable = "\x1b[?1006l\x1b[?1003l\x1b[?1002l\x1b[?1000l";
+    out.write_all(ansi_disable.as_bytes())?;
+    return Ok(());
+}
+
+pub(crate) fn paste_enable(out: &mut impl Write) -> Result<()> {
+    let ansi_enable = "\x1b[?2004h";
+    out.write_all(ansi_enable.as_bytes())?;
+    return Ok(());
+}
+
+pub(crate) fn paste_disable(out: &mut impl Write) -> Result<()> {
+    let ansi_disable = "\x1b[?2004l";
+    out.write_all(ansi_disable.as_bytes())?;
+    return Ok(());
+}
+
+pub(crate) fn focus_enable(out: &mut impl Write) -> Result<()> {
+    let ansi_enable = "\x1b[?1004h";
+    out.write_all(ansi_enable.as_bytes())?;
+    return Ok(());
+}
+
+pub(crate) fn focus_disable(out: &mut impl Write) -> Result<()> {
+    let ansi_disable = "\x1b[?1004l";
+    out.write_all(ansi_disable.as_bytes())?;
+    return Ok(());
+}
+
+pub(crate) fn alternate_screen_enter(out: &mut impl Write) -> Result<()> {
+    alternate_screen_write(out, true)?;
+    ALTERNATE_SCREEN.store(true, Ordering::Relaxed);
+    return Ok(());
+}
+
+pub(crate) fn alternate_screen_leave(out: &mut impl Write) -> Result<()> {
+    ALTERNATE_SCREEN.store(false, Ordering::Relaxed);
+    return alternate_screen_write(out, false);
+}
+
+/// Writes the sequence switching to or from the alternate screen without remembering to leave
+/// it later.
+pub(crate) fn alternate_screen_write(out: &mut impl Write, enter: bool) -> Result<()> {
+    let ansi_switch = if enter { "\x1b[?1049h" } else { "\x1b[?1049l" };
+    out.write_all(ansi_switch.as_bytes())?;
+    return Ok(());
 }
 
-pub(crate) fn color_bg(red: u8, green: u8, blue: u8) {
-    let mut buffer = [0u8; 32];
-    write!(&mut buffer[..], "\x1b[48;2;{red};{green};{blue}m").unwrap();
-    print_buf(&buffer, buffer.len());
+/// Leaves the alternate screen if it is shown, used when the terminal gets restored.
+pub(crate) fn alternate_screen_restore() {
+    if ALTERNATE_SCREEN.load(Ordering::Relaxed) {
+        let _ = print_with(alternate_screen_leave);
+    }
 }
 
-pub(crate) fn color_fg(red: u8, green: u8, blue: u8) {
-    let mut buffer = [0u8; 32];
-    write!(&mut buffer[..], "\x1b[38;2;{red};{green};{blue}m").unwrap();
-    print_buf(&buffer, buffer.len());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use crate::{KeyCode, Modifiers};
+
+    /// Terminal which answers with `input` and runs dry afterwards instead of blocking.
+    struct Scripted {
+        input:  RefCell<Vec<u8>>,
+        output: RefCell<Vec<u8>>,
+        // Set once a read had to wait for input that never came.
+        dry: Cell<bool>,
+    }
+
+    impl Scripted {
+        fn new(input: &[u8]) -> Scripted {
+            return Scripted {
+                input:  RefCell::new(input.to_vec()),
+                output: RefCell::new(Vec::new()),
+                dry: Cell::new(false),
+            };
+        }
+    }
+
+    impl Device for Scripted {
+        fn input_wait(&self, _timeout: Option<Duration>) -> Wake {
+            if self.input.borrow().is_empty() {
+                self.dry.set(true);
+                return Wake::Timeout;
+            }
+
+            return Wake::Input;
+        }
+
+        fn input_read(&self, buffer: &mut [u8]) -> Result<usize> {
+            let mut input = self.input.borrow_mut();
+            let length = input.len().min(buffer.len());
+            buffer[..length].copy_from_slice(&input[..length]);
+            input.drain(..length);
+            return Ok(length);
+        }
+
+        fn output_write(&self, buffer: &[u8]) -> Result<()> {
+            self.output.borrow_mut().extend_from_slice(buffer);
+            return Ok(());
+        }
+
+        fn size(&self) -> Result<Pos> {
+            return Ok(Pos { x: 80, y: 24 });
+        }
+    }
+
+    #[test]
+    fn cursor_get() {
+        let device = Scripted::new(b"a\x1b[5;10R");
+        let mut parser = Parser::new();
+        assert_eq!(device_cursor_get(&device, &mut parser).ok(), Some(Pos { x: 9, y: 4 }));
+        assert_eq!(*device.output.borrow(), b"\x1b[6n");
+
+        // Input which arrived before the report stays queued.
+        assert_eq!(parser.next(), Some(Event::Key(KeyEvent::new(KeyCode::Char('a'), Modifiers::NONE))));
+    }
+
+    #[test]
+    fn cursor_get_malformed() {
+        let device = Scripted::new(b"\x1b[;R");
+        let mut parser = Parser::new();
+        assert!(matches!(device_cursor_get(&device, &mut parser), Err(Error::MalformedResponse)));
+        assert!(!device.dry.get());
+        assert_eq!(parser.next(), None);
+    }
+
+    #[test]
+    fn cursor_get_unanswered() {
+        let device = Scripted::new(b"");
+        let mut parser = Parser::new();
+        assert!(matches!(device_cursor_get(&device, &mut parser), Err(Error::Timeout)));
+
+        // A late report is not expected any more.
+        parser.advance(b"\x1b[1;5R");
+        assert_eq!(parser.next(), Some(Event::Key(KeyEvent::new(KeyCode::F(3), Modifiers::CTRL))));
+    }
 }
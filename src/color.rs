@@ -0,0 +1,489 @@
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use crate::{Color, ColorDepth, StylePolicy};
+
+// NOTE: Colours are sent as they are until `terma_init` detects what the terminal can show.
+static COLOR_DEPTH: AtomicU8 = AtomicU8::new(ColorDepth::TrueColor as u8);
+
+static STYLE_POLICY: AtomicU8 = AtomicU8::new(StylePolicy::Auto as u8);
+
+// NOTE: What `StylePolicy::Auto` comes down to, decided by `terma_init`.
+static STYLE_AUTO: AtomicBool = AtomicBool::new(true);
+
+// Index of the `colors` number capability in compiled terminfo entries.
+const TERMINFO_COLORS: usize = 13;
+
+// Default xterm values for the named colours, which is what most terminals start out with.
+const NAMED_RGB: [[u8; 3]; 16] = [
+    [  0,   0,   0], [205,   0,   0], [  0, 205,   0], [205, 205,   0],
+    [  0,   0, 238], [205,   0, 205], [  0, 205, 205], [229, 229, 229],
+    [127, 127, 127], [255,   0,   0], [  0, 255,   0], [255, 255,   0],
+    [ 92,  92, 255], [255,   0, 255], [  0, 255, 255], [255, 255, 255],
+];
+
+// Channel values of the 6x6x6 colour cube in the 256 colour palette.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+pub(crate) fn depth() -> ColorDepth {
+    return match COLOR_DEPTH.load(Ordering::Relaxed) {
+        0 => ColorDepth::None,
+        1 => ColorDepth::Ansi16,
+        2 => ColorDepth::Indexed256,
+        _ => ColorDepth::TrueColor,
+    };
+}
+
+pub(crate) fn depth_set(depth: ColorDepth) {
+    COLOR_DEPTH.store(depth as u8, Ordering::Relaxed);
+}
+
+pub(crate) fn style_policy() -> StylePolicy {
+    return match STYLE_POLICY.load(Ordering::Relaxed) {
+        0 => StylePolicy::Auto,
+        1 => StylePolicy::Always,
+        _ => StylePolicy::Never,
+    };
+}
+
+pub(crate) fn style_policy_set(policy: StylePolicy) {
+    STYLE_POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
+/// Returns true when colours and text attributes should be sent to the standard output.
+pub(crate) fn style_enabled() -> bool {
+    return style_enabled_with(STYLE_AUTO.load(Ordering::Relaxed));
+}
+
+/// Same as `style_enabled`, with `auto` being what `StylePolicy::Auto` comes down to.
+pub(crate) fn style_enabled_with(auto: bool) -> bool {
+    match style_policy() {
+        StylePolicy::Auto   => return auto,
+        StylePolicy::Always => return true,
+        StylePolicy::Never  => return false,
+    }
+}
+
+/// Decides what `StylePolicy::Auto` comes down to for the standard output.
+pub(crate) fn style_detect(is_tty: bool) {
+    STYLE_AUTO.store(style_auto(is_tty), Ordering::Relaxed);
+}
+
+/// Output is styled when it goes to a terminal, unless CLICOLOR turns it off or CLICOLOR_FORCE
+/// and FORCE_COLOR force it one way or the other.
+pub(crate) fn style_auto(is_tty: bool) -> bool {
+    return style_auto_with(is_tty, depth_forced(), env::var("CLICOLOR").ok().as_deref());
+}
+
+/// Same as `style_auto`, with the forced colour depth and CLICOLOR passed in.
+fn style_auto_with(is_tty: bool, forced: Option<ColorDepth>, clicolor: Option<&str>) -> bool {
+    match forced {
+        Some(depth) => return depth != ColorDepth::None,
+        None => return is_tty && clicolor != Some("0"),
+    }
+}
+
+/// Colour depth forced through FORCE_COLOR or CLICOLOR_FORCE, as a lower bound for what the
+/// terminal is detected as.
+fn depth_forced() -> Option<ColorDepth> {
+    let force_color    = env::var("FORCE_COLOR").ok();
+    let clicolor_force = env::var("CLICOLOR_FORCE").ok();
+    return depth_forced_with(force_color.as_deref(), clicolor_force.as_deref());
+}
+
+/// Same as `depth_forced`, with the values of FORCE_COLOR and CLICOLOR_FORCE passed in.
+// NOTE: Variables which are set but empty count as unset.
+fn depth_forced_with(force_color: Option<&str>, clicolor_force: Option<&str>) -> Option<ColorDepth> {
+    match force_color {
+        None | Some("")     => {}
+        Some("0" | "false") => return Some(ColorDepth::None),
+        Some("2")           => return Some(ColorDepth::Indexed256),
+        Some("3")           => return Some(ColorDepth::TrueColor),
+        Some(_)             => return Some(ColorDepth::Ansi16),
+    }
+
+    if clicolor_force.is_some_and(|value| !value.is_empty() && value != "0") {
+        return Some(ColorDepth::Ansi16);
+    }
+
+    return None;
+}
+
+/// Works out the colour depth from the environment and the terminfo database, going with
+/// `fallback` when nothing says otherwise.
+pub(crate) fn depth_detect(fallback: ColorDepth) -> ColorDepth {
+    let no_color = env::var_os("NO_COLOR");
+    return depth_detect_with(depth_forced(), no_color.as_deref(), || depth_terminal(fallback));
+}
+
+/// Same as `depth_detect`, with the forced colour depth, NO_COLOR and the colour depth of the
+/// terminal passed in.
+fn depth_detect_with(
+    forced: Option<ColorDepth>,
+    no_color: Option<&OsStr>,
+    terminal: impl FnOnce() -> ColorDepth,
+) -> ColorDepth {
+    match forced {
+        Some(ColorDepth::None) => return ColorDepth::None,
+        Some(depth) => return terminal().max(depth),
+        None => {}
+    }
+
+    // NOTE: Only turns off colours, text attributes are still fine.
+    if no_color.is_some_and(|value| !value.is_empty()) {
+        return ColorDepth::None;
+    }
+
+    return terminal();
+}
+
+/// Colour depth of the terminal according to COLORTERM, TERM and the terminfo database.
+fn depth_terminal(fallback: ColorDepth) -> ColorDepth {
+    if let Ok(color_term) = env::var("COLORTERM") {
+        if color_term == "truecolor" || color_term == "24bit" {
+            return ColorDepth::TrueColor;
+        }
+    }
+
+    let term = env::var("TERM").unwrap_or_default();
+    if term.is_empty() {
+        return fallback;
+    }
+
+    if term == "dumb" {
+        return ColorDepth::None;
+    }
+
+    if term.ends_with("-direct") || term.contains("truecolor") || term.contains("24bit") {
+        return ColorDepth::TrueColor;
+    }
+
+    match terminfo_colors(&term) {
+        Some(colors) if colors >= 1 << 24 => return ColorDepth::TrueColor,
+        Some(colors) if colors >= 256     => return ColorDepth::Indexed256,
+        Some(colors) if colors >= 8       => return ColorDepth::Ansi16,
+        Some(_)                           => return ColorDepth::None,
+        None => {}
+    }
+
+    if term.contains("256color") {
+        return ColorDepth::Indexed256;
+    }
+
+    return ColorDepth::Ansi16;
+}
+
+/// Looks up the `colors` capability of `term` in the terminfo database.
+fn terminfo_colors(term: &str) -> Option<i32> {
+    let first = term.chars().next()?;
+
+    let mut directories = Vec::new();
+    if let Some(directory) = env::var_os("TERMINFO") {
+        directories.push(PathBuf::from(directory));
+    }
+
+    if let Some(home) = env::var_os("HOME") {
+        directories.push(PathBuf::from(home).join(".terminfo"));
+    }
+
+    if let Ok(terminfo_dirs) = env::var("TERMINFO_DIRS") {
+        for directory in terminfo_dirs.split(':').filter(|directory| !directory.is_empty()) {
+            directories.push(PathBuf::from(directory));
+        }
+    }
+
+    for directory in ["/etc/terminfo", "/lib/terminfo", "/usr/share/terminfo", "/usr/lib/terminfo"] {
+        directories.push(PathBuf::from(directory));
+    }
+
+    // Entries are grouped by their first letter, or its hex code on macOS.
+    let groups = [first.to_string(), format!("{:x}", first as u32)];
+    for directory in directories {
+        for group in &groups {
+            if let Ok(data) = fs::read(directory.join(group).join(term)) {
+                return terminfo_parse_colors(&data);
+            }
+        }
+    }
+
+    return None;
+}
+
+/// Reads the `colors` capability out of a compiled terminfo entry.
+fn terminfo_parse_colors(data: &[u8]) -> Option<i32> {
+    let header = |index: usize| -> Option<i16> {
+        let bytes = data.get(index * 2..index * 2 + 2)?;
+        return Some(i16::from_le_bytes([bytes[0], bytes[1]]));
+    };
+
+    // The newer format stores numbers as 32 bits instead of 16.
+    let number_size = match header(0)? {
+        0o432  => 2,
+        0o1036 => 4,
+        _ => return None,
+    };
+
+    let names_size    = usize::try_from(header(1)?).ok()?;
+    let bools_count   = usize::try_from(header(2)?).ok()?;
+    let numbers_count = usize::try_from(header(3)?).ok()?;
+    // Missing capabilities read as -1, same as ones that are cancelled.
+    if numbers_count <= TERMINFO_COLORS {
+        return Some(-1);
+    }
+
+    // Numbers start on an even offset after the header, names and booleans.
+    let mut offset = 12 + names_size + bools_count;
+    offset += offset % 2;
+
+    let start = offset + TERMINFO_COLORS * number_size;
+    let bytes = data.get(start..start + number_size)?;
+    let colors = match number_size {
+        2 => i16::from_le_bytes([bytes[0], bytes[1]]) as i32,
+        _ => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+    };
+
+    return Some(colors);
+}
+
+/// Maps `color` onto the closest one a terminal with the given colour depth can show, or
+/// `None` when it can not show colours at all.
+pub(crate) fn downsample(color: Color, depth: ColorDepth) -> Option<Color> {
+    match (depth, color) {
+        (ColorDepth::None, _) => return None,
+        (ColorDepth::TrueColor, _) | (_, Color::Default) => return Some(color),
+
+        (ColorDepth::Indexed256, Color::Rgb { red, green, blue }) => {
+            // NOTE: The named colours are left out, users tend to change them in their themes.
+            let index = nearest([red, green, blue], 16..=255);
+            return Some(Color::Indexed(index));
+        }
+        (ColorDepth::Indexed256, _) => return Some(color),
+
+        (ColorDepth::Ansi16, Color::Rgb { red, green, blue }) => {
+            let index = nearest([red, green, blue], 0..=15);
+            return Some(Color::NAMED[index as usize]);
+        }
+        (ColorDepth::Ansi16, Color::Indexed(index)) if index >= 16 => {
+            let index = nearest(palette_rgb(index), 0..=15);
+            return Some(Color::NAMED[index as usize]);
+        }
+        (ColorDepth::Ansi16, Color::Indexed(index)) => return Some(Color::NAMED[index as usize]),
+        (ColorDepth::Ansi16, _) => return Some(color),
+    }
+}
+
+/// Colour of a palette entry, assuming the default xterm palette.
+fn palette_rgb(index: u8) -> [u8; 3] {
+    match index {
+        0..=15 => return NAMED_RGB[index as usize],
+        16..=231 => {
+            let cube = index - 16;
+            let red   = CUBE_LEVELS[(cube / 36) as usize];
+            let green = CUBE_LEVELS[(cube / 6 % 6) as usize];
+            let blue  = CUBE_LEVELS[(cube % 6) as usize];
+            return [red, green, blue];
+        }
+        _ => {
+            let grey = 8 + (index - 232) * 10;
+            return [grey, grey, grey];
+        }
+    }
+}
+
+/// Palette entry out of `candidates` which looks the most like `rgb`.
+fn nearest(rgb: [u8; 3], candidates: std::ops::RangeInclusive<u8>) -> u8 {
+    let target = oklab(rgb);
+
+    let mut best_index = *candidates.start();
+    let mut best_distance = f32::MAX;
+    for index in candidates {
+        let [l, a, b] = oklab(palette_rgb(index));
+        let distance = (l - target[0]).powi(2) + (a - target[1]).powi(2) + (b - target[2]).powi(2);
+        if distance < best_distance {
+            best_distance = distance;
+            best_index = index;
+        }
+    }
+
+    return best_index;
+}
+
+/// Converts sRGB into the Oklab colour space, where distances match how different colours
+/// look to people much better than they do in RGB.
+fn oklab(rgb: [u8; 3]) -> [f32; 3] {
+    let [red, green, blue] = rgb.map(|channel| {
+        let channel = channel as f32 / 255.0;
+        if channel <= 0.04045 {
+            return channel / 12.92;
+        }
+
+        return ((channel + 0.055) / 1.055).powf(2.4);
+    });
+
+    let l = (0.412_221_47 * red + 0.536_332_55 * green + 0.051_445_995 * blue).cbrt();
+    let m = (0.211_903_5  * red + 0.680_699_5  * green + 0.107_396_96  * blue).cbrt();
+    let s = (0.088_302_46 * red + 0.281_718_85 * green + 0.629_978_7   * blue).cbrt();
+
+    return [
+        0.210_454_26 * l + 0.793_617_8  * m - 0.004_072_047 * s,
+        1.977_998_5  * l - 2.428_592_2  * m + 0.450_593_7   * s,
+        0.025_904_037 * l + 0.782_771_77 * m - 0.808_675_77  * s,
+    ];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Compiled terminfo entry with the given names, booleans and numbers and no strings.
+    fn terminfo(magic: i16, names: &[u8], bools_count: usize, numbers: &[i32], number_size: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        for value in [magic, names.len() as i16, bools_count as i16, numbers.len() as i16, 0, 0] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+
+        data.extend_from_slice(names);
+        data.resize(data.len() + bools_count, 1);
+        if data.len() % 2 != 0 {
+            data.push(0);
+        }
+
+        for &number in numbers {
+            match number_size {
+                2 => data.extend_from_slice(&(number as i16).to_le_bytes()),
+                _ => data.extend_from_slice(&number.to_le_bytes()),
+            }
+        }
+
+        return data;
+    }
+
+    fn numbers_with_colors(colors: i32) -> Vec<i32> {
+        let mut numbers = vec![-1; 15];
+        numbers[0] = 80;
+        numbers[TERMINFO_COLORS] = colors;
+        return numbers;
+    }
+
+    #[test]
+    fn force_color() {
+        assert_eq!(depth_forced_with(None,          None), None);
+        assert_eq!(depth_forced_with(Some(""),      None), None);
+        assert_eq!(depth_forced_with(Some("0"),     None), Some(ColorDepth::None));
+        assert_eq!(depth_forced_with(Some("false"), None), Some(ColorDepth::None));
+        assert_eq!(depth_forced_with(Some("1"),     None), Some(ColorDepth::Ansi16));
+        assert_eq!(depth_forced_with(Some("true"),  None), Some(ColorDepth::Ansi16));
+        assert_eq!(depth_forced_with(Some("2"),     None), Some(ColorDepth::Indexed256));
+        assert_eq!(depth_forced_with(Some("3"),     None), Some(ColorDepth::TrueColor));
+    }
+
+    #[test]
+    fn clicolor_force() {
+        assert_eq!(depth_forced_with(None,      Some("1")), Some(ColorDepth::Ansi16));
+        assert_eq!(depth_forced_with(None,      Some("0")), None);
+        assert_eq!(depth_forced_with(None,      Some("")),  None);
+        assert_eq!(depth_forced_with(Some(""),  Some("1")), Some(ColorDepth::Ansi16));
+
+        // FORCE_COLOR wins over CLICOLOR_FORCE.
+        assert_eq!(depth_forced_with(Some("0"), Some("1")), Some(ColorDepth::None));
+        assert_eq!(depth_forced_with(Some("3"), Some("1")), Some(ColorDepth::TrueColor));
+    }
+
+    #[test]
+    fn style_auto_rules() {
+        assert!( style_auto_with(true,  None, None));
+        assert!(!style_auto_with(false, None, None));
+        assert!(!style_auto_with(true,  None, Some("0")));
+        assert!( style_auto_with(true,  None, Some("1")));
+        assert!( style_auto_with(false, Some(ColorDepth::Ansi16), None));
+        assert!( style_auto_with(false, Some(ColorDepth::Ansi16), Some("0")));
+        assert!(!style_auto_with(true,  Some(ColorDepth::None),   None));
+    }
+
+    #[test]
+    fn no_color() {
+        let terminal = || ColorDepth::TrueColor;
+        assert_eq!(depth_detect_with(None, None,                  terminal), ColorDepth::TrueColor);
+        assert_eq!(depth_detect_with(None, Some(OsStr::new("1")), terminal), ColorDepth::None);
+        assert_eq!(depth_detect_with(None, Some(OsStr::new("")),  terminal), ColorDepth::TrueColor);
+
+        // Forcing colours wins over NO_COLOR, and raises the depth of the terminal.
+        assert_eq!(depth_detect_with(Some(ColorDepth::Ansi16),     Some(OsStr::new("1")), terminal), ColorDepth::TrueColor);
+        assert_eq!(depth_detect_with(Some(ColorDepth::Indexed256), None, || ColorDepth::Ansi16), ColorDepth::Indexed256);
+        assert_eq!(depth_detect_with(Some(ColorDepth::None),       None, terminal), ColorDepth::None);
+    }
+
+    #[test]
+    fn terminfo_legacy() {
+        // 12 bytes of header, 8 of names and 3 booleans, so the numbers need padding.
+        let data = terminfo(0o432, b"xterm|x\0", 3, &numbers_with_colors(8), 2);
+        assert_eq!(terminfo_parse_colors(&data), Some(8));
+
+        let data = terminfo(0o432, b"xterm-256color\0", 2, &numbers_with_colors(256), 2);
+        assert_eq!(terminfo_parse_colors(&data), Some(256));
+    }
+
+    #[test]
+    fn terminfo_extended_numbers() {
+        let data = terminfo(0o1036, b"xterm-direct\0", 3, &numbers_with_colors(1 << 24), 4);
+        assert_eq!(terminfo_parse_colors(&data), Some(1 << 24));
+    }
+
+    #[test]
+    fn terminfo_colors_absent() {
+        // Cancelled.
+        let data = terminfo(0o432, b"vt100\0", 2, &numbers_with_colors(-2), 2);
+        assert_eq!(terminfo_parse_colors(&data), Some(-2));
+
+        // Not among the numbers at all.
+        let data = terminfo(0o432, b"vt100\0", 2, &[80, -1, 24], 2);
+        assert_eq!(terminfo_parse_colors(&data), Some(-1));
+    }
+
+    #[test]
+    fn terminfo_invalid() {
+        assert_eq!(terminfo_parse_colors(b""), None);
+        assert_eq!(terminfo_parse_colors(&terminfo(0o433, b"x\0", 0, &numbers_with_colors(8), 2)), None);
+
+        let mut data = terminfo(0o432, b"x\0", 0, &numbers_with_colors(8), 2);
+        data.truncate(data.len() - 4);
+        assert_eq!(terminfo_parse_colors(&data), None);
+    }
+
+    #[test]
+    fn palette() {
+        assert_eq!(palette_rgb(1),   [205,   0,   0]);
+        assert_eq!(palette_rgb(16),  [  0,   0,   0]);
+        assert_eq!(palette_rgb(196), [255,   0,   0]);
+        assert_eq!(palette_rgb(231), [255, 255, 255]);
+        assert_eq!(palette_rgb(232), [  8,   8,   8]);
+        assert_eq!(palette_rgb(255), [238, 238, 238]);
+    }
+
+    #[test]
+    fn nearest_exact() {
+        for index in 0..=255 {
+            assert_eq!(palette_rgb(nearest(palette_rgb(index), 0..=255)), palette_rgb(index));
+        }
+    }
+
+    #[test]
+    fn downsample_known() {
+        let red = Color::Rgb { red: 255, green: 0, blue: 0 };
+        assert_eq!(downsample(red, ColorDepth::TrueColor),  Some(red));
+        assert_eq!(downsample(red, ColorDepth::Indexed256), Some(Color::Indexed(196)));
+        assert_eq!(downsample(red, ColorDepth::Ansi16),     Some(Color::BrightRed));
+        assert_eq!(downsample(red, ColorDepth::None),       None);
+
+        let grey = Color::Rgb { red: 10, green: 10, blue: 10 };
+        assert_eq!(downsample(grey, ColorDepth::Indexed256), Some(Color::Indexed(232)));
+
+        assert_eq!(downsample(Color::Indexed(196), ColorDepth::Ansi16), Some(Color::BrightRed));
+        assert_eq!(downsample(Color::Indexed(5),   ColorDepth::Ansi16), Some(Color::Magenta));
+        assert_eq!(downsample(Color::Indexed(200), ColorDepth::Indexed256), Some(Color::Indexed(200)));
+        assert_eq!(downsample(Color::Default,      ColorDepth::Ansi16), Some(Color::Default));
+        assert_eq!(downsample(Color::Blue,         ColorDepth::Ansi16), Some(Color::Blue));
+    }
+}
@@ -0,0 +1,395 @@
+use std::fmt;
+use std::io;
+use crate::ansi;
+use crate::{Attributes, ClearType, Color, CursorShape, MouseMode, Result};
+
+/// Terminal operation which can be written into any output, like a `Vec<u8>`, a socket or a
+/// `Terminal`. Every command also implements `Display`, so it can be used with `write!`.
+///
+/// Commands only write escape sequences, they do nothing on the legacy Windows console.
+/// Colours and attributes are written exactly as given, regardless of `style_policy` and
+/// `color_depth`. Use `Color::downsample` to pick colours the terminal can show.
+pub trait Command {
+    fn write_ansi(&self, out: &mut dyn io::Write) -> Result<()>;
+}
+
+impl<T: Command + ?Sized> Command for &T {
+    fn write_ansi(&self, out: &mut dyn io::Write) -> Result<()> {
+        return (**self).write_ansi(out);
+    }
+}
+
+/// Moves the cursor to column `x` and row `y`, counting from 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CursorSet {
+    pub x: u16,
+    pub y: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CursorUp(pub u16);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CursorDown(pub u16);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CursorLeft(pub u16);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CursorRight(pub u16);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CursorLineNext(pub u16);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CursorLinePrev(pub u16);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CursorColumn(pub u16);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CursorSave;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CursorRestore;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CursorHide;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CursorShow;
+
+/// Changes the look of the cursor. Unlike `cursor_shape` it is not reset by `RawMode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CursorShapeSet(pub CursorShape);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConsoleClear;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConsoleErase(pub ClearType);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColorReset;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColorFg(pub Color);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColorBg(pub Color);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColorUnderline(pub Color);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AttributesSet(pub Attributes);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AttributesClear(pub Attributes);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEnable(pub MouseMode);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseDisable;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PasteEnable;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PasteDisable;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FocusEnable;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FocusDisable;
+
+/// Switches to the alternate screen. Unlike `alternate_screen_enter` it is not left again by
+/// `RawMode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AlternateScreenEnter;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AlternateScreenLeave;
+
+impl Command for CursorSet {
+    fn write_ansi(&self, mut out: &mut dyn io::Write) -> Result<()> {
+        return ansi::cursor_set(&mut out, self.x, self.y);
+    }
+}
+
+impl Command for CursorUp {
+    fn write_ansi(&self, mut out: &mut dyn io::Write) -> Result<()> {
+        return ansi::cursor_up(&mut out, self.0);
+    }
+}
+
+impl Command for CursorDown {
+    fn write_ansi(&self, mut out: &mut dyn io::Write) -> Result<()> {
+        return ansi::cursor_down(&mut out, self.0);
+    }
+}
+
+impl Command for CursorLeft {
+    fn write_ansi(&self, mut out: &mut dyn io::Write) -> Result<()> {
+        return ansi::cursor_left(&mut out, self.0);
+    }
+}
+
+impl Command for CursorRight {
+    fn write_ansi(&self, mut out: &mut dyn io::Write) -> Result<()> {
+        return ansi::cursor_right(&mut out, self.0);
+    }
+}
+
+impl Command for CursorLineNext {
+    fn write_ansi(&self, mut out: &mut dyn io::Write) -> Result<()> {
+        return ansi::cursor_line_next(&mut out, self.0);
+    }
+}
+
+impl Command for CursorLinePrev {
+    fn write_ansi(&self, mut out: &mut dyn io::Write) -> Result<()> {
+        return ansi::cursor_line_prev(&mut out, self.0);
+    }
+}
+
+impl Command for CursorColumn {
+    fn write_ansi(&self, mut out: &mut dyn io::Write) -> Result<()> {
+        return ansi::cursor_column(&mut out, self.0);
+    }
+}
+
+impl Command for CursorSave {
+    fn write_ansi(&self, mut out: &mut dyn io::Write) -> Result<()> {
+        return ansi::cursor_save(&mut out);
+    }
+}
+
+impl Command for CursorRestore {
+    fn write_ansi(&self, mut out: &mut dyn io::Write) -> Result<()> {
+        return ansi::cursor_restore(&mut out);
+    }
+}
+
+impl Command for CursorHide {
+    fn write_ansi(&self, mut out: &mut dyn io::Write) -> Result<()> {
+        return ansi::cursor_hide(&mut out);
+    }
+}
+
+impl Command for CursorShow {
+    fn write_ansi(&self, mut out: &mut dyn io::Write) -> Result<()> {
+        return ansi::cursor_show(&mut out);
+    }
+}
+
+impl Command for CursorShapeSet {
+    fn write_ansi(&self, mut out: &mut dyn io::Write) -> Result<()> {
+        return ansi::cursor_shape_write(&mut out, self.0);
+    }
+}
+
+impl Command for ConsoleClear {
+    fn write_ansi(&self, mut out: &mut dyn io::Write) -> Result<()> {
+        return ansi::console_clear(&mut out);
+    }
+}
+
+impl Command for ConsoleErase {
+    fn write_ansi(&self, mut out: &mut dyn io::Write) -> Result<()> {
+        return ansi::console_erase(&mut out, self.0);
+    }
+}
+
+impl Command for ColorReset {
+    fn write_ansi(&self, mut out: &mut dyn io::Write) -> Result<()> {
+        return ansi::color_reset(&mut out, true);
+    }
+}
+
+impl Command for ColorFg {
+    fn write_ansi(&self, mut out: &mut dyn io::Write) -> Result<()> {
+        return ansi::color_write(&mut out, self.0, 30);
+    }
+}
+
+impl Command for ColorBg {
+    fn write_ansi(&self, mut out: &mut dyn io::Write) -> Result<()> {
+        return ansi::color_write(&mut out, self.0, 40);
+    }
+}
+
+impl Command for ColorUnderline {
+    fn write_ansi(&self, mut out: &mut dyn io::Write) -> Result<()> {
+        return ansi::color_write(&mut out, self.0, 50);
+    }
+}
+
+impl Command for AttributesSet {
+    fn write_ansi(&self, mut out: &mut dyn io::Write) -> Result<()> {
+        return ansi::attributes_set(&mut out, true, self.0);
+    }
+}
+
+impl Command for AttributesClear {
+    fn write_ansi(&self, mut out: &mut dyn io::Write) -> Result<()> {
+        return ansi::attributes_clear(&mut out, true, self.0);
+    }
+}
+
+impl Command for MouseEnable {
+    fn write_ansi(&self, mut out: &mut dyn io::Write) -> Result<()> {
+        return ansi::mouse_enable(&mut out, self.0);
+    }
+}
+
+impl Command for MouseDisable {
+    fn write_ansi(&self, mut out: &mut dyn io::Write) -> Result<()> {
+        return ansi::mouse_disable(&mut out);
+    }
+}
+
+impl Command for PasteEnable {
+    fn write_ansi(&self, mut out: &mut dyn io::Write) -> Result<()> {
+        return ansi::paste_enable(&mut out);
+    }
+}
+
+impl Command for PasteDisable {
+    fn write_ansi(&self, mut out: &mut dyn io::Write) -> Result<()> {
+        return ansi::paste_disable(&mut out);
+    }
+}
+
+impl Command for FocusEnable {
+    fn write_ansi(&self, mut out: &mut dyn io::Write) -> Result<()> {
+        return ansi::focus_enable(&mut out);
+    }
+}
+
+impl Command for FocusDisable {
+    fn write_ansi(&self, mut out: &mut dyn io::Write) -> Result<()> {
+        return ansi::focus_disable(&mut out);
+    }
+}
+
+impl Command for AlternateScreenEnter {
+    fn write_ansi(&self, mut out: &mut dyn io::Write) -> Result<()> {
+        return ansi::alternate_screen_write(&mut out, true);
+    }
+}
+
+impl Command for AlternateScreenLeave {
+    fn write_ansi(&self, mut out: &mut dyn io::Write) -> Result<()> {
+        return ansi::alternate_screen_write(&mut out, false);
+    }
+}
+
+/// Lets a command write itself into a `fmt::Formatter`.
+struct FormatterWriter<'a, 'b>(&'a mut fmt::Formatter<'b>);
+
+impl io::Write for FormatterWriter<'_, '_> {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        // NOTE: Escape sequences are plain ASCII, so this only fails on a broken formatter.
+        let string = std::str::from_utf8(buffer).map_err(io::Error::other)?;
+        self.0.write_str(string).map_err(io::Error::other)?;
+        return Ok(buffer.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
+
+fn command_fmt(command: &dyn Command, f: &mut fmt::Formatter) -> fmt::Result {
+    return command.write_ansi(&mut FormatterWriter(f)).map_err(|_| fmt::Error);
+}
+
+// NOTE: `Display` can not be implemented for every `Command` at once, it is not our trait.
+macro_rules! display_impl {
+    ($($command:ty),* $(,)?) => {
+        $(
+            impl fmt::Display for $command {
+                fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    return command_fmt(self, f);
+                }
+            }
+        )*
+    };
+}
+
+display_impl!(
+    CursorSet, CursorUp, CursorDown, CursorLeft, CursorRight, CursorLineNext, CursorLinePrev,
+    CursorColumn, CursorSave, CursorRestore, CursorHide, CursorShow, CursorShapeSet,
+    ConsoleClear, ConsoleErase, ColorReset, ColorFg, ColorBg, ColorUnderline, AttributesSet,
+    AttributesClear, MouseEnable, MouseDisable, PasteEnable, PasteDisable, FocusEnable,
+    FocusDisable, AlternateScreenEnter, AlternateScreenLeave,
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(command: impl Command) -> Vec<u8> {
+        let mut out = Vec::new();
+        command.write_ansi(&mut out).unwrap();
+        return out;
+    }
+
+    #[test]
+    fn cursor() {
+        assert_eq!(bytes(CursorSet { x: 0, y: 0 }), b"\x1b[1;1H");
+        assert_eq!(bytes(CursorSet { x: 9, y: 4 }), b"\x1b[5;10H");
+        assert_eq!(bytes(CursorUp(3)),              b"\x1b[3A");
+        assert_eq!(bytes(CursorUp(0)),              b"");
+        assert_eq!(bytes(CursorLineNext(0)),        b"");
+        assert_eq!(bytes(CursorLinePrev(2)),        b"\x1b[2F");
+        assert_eq!(bytes(CursorHide),               b"\x1b[?25l");
+        assert_eq!(bytes(CursorShow),               b"\x1b[?25h");
+    }
+
+    #[test]
+    fn erase() {
+        assert_eq!(bytes(ConsoleClear),                            b"\x1b[1;1H\x1b[0J");
+        assert_eq!(bytes(ConsoleErase(ClearType::LineEnd)),       b"\x1b[0K");
+        assert_eq!(bytes(ConsoleErase(ClearType::Screen)),        b"\x1b[2J");
+        assert_eq!(bytes(ConsoleErase(ClearType::Characters(5))), b"\x1b[5X");
+        assert_eq!(bytes(ConsoleErase(ClearType::Characters(0))), b"");
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(bytes(ColorReset),                                        b"\x1b[0m");
+        assert_eq!(bytes(ColorFg(Color::Red)),                               b"\x1b[31m");
+        assert_eq!(bytes(ColorFg(Color::BrightRed)),                         b"\x1b[91m");
+        assert_eq!(bytes(ColorBg(Color::Blue)),                              b"\x1b[44m");
+        assert_eq!(bytes(ColorBg(Color::BrightWhite)),                       b"\x1b[107m");
+        assert_eq!(bytes(ColorFg(Color::Default)),                           b"\x1b[39m");
+        assert_eq!(bytes(ColorBg(Color::Indexed(200))),                      b"\x1b[48;5;200m");
+        assert_eq!(bytes(ColorUnderline(Color::Green)),                      b"\x1b[58;5;2m");
+        assert_eq!(bytes(ColorFg(Color::Rgb { red: 1, green: 2, blue: 3 })), b"\x1b[38;2;1;2;3m");
+    }
+
+    #[test]
+    fn attributes() {
+        assert_eq!(bytes(AttributesSet(Attributes::BOLD | Attributes::ITALIC)),              b"\x1b[1;3m");
+        assert_eq!(bytes(AttributesSet(Attributes::UNDERLINE | Attributes::CURLY_UNDERLINE)), b"\x1b[4:3m");
+        assert_eq!(bytes(AttributesSet(Attributes::NONE)),                                    b"");
+        assert_eq!(bytes(AttributesClear(Attributes::DIM | Attributes::STRIKETHROUGH)),      b"\x1b[22;29m");
+    }
+
+    #[test]
+    fn modes() {
+        assert_eq!(bytes(MouseEnable(MouseMode::Drag)), b"\x1b[?1002h\x1b[?1006h");
+        assert_eq!(bytes(PasteEnable),                   b"\x1b[?2004h");
+        assert_eq!(bytes(FocusDisable),                  b"\x1b[?1004l");
+        assert_eq!(bytes(AlternateScreenEnter),          b"\x1b[?1049h");
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(format!("{}x{}", ColorFg(Color::Red), ColorReset), "\x1b[31mx\x1b[0m");
+    }
+}
@@ -0,0 +1,54 @@
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Reading from or writing to the terminal failed.
+    Io(io::Error),
+    /// The input or output is not connected to a terminal.
+    NotATty,
+    /// The terminal did not answer a query in time.
+    Timeout,
+    /// The terminal answered a query with something that could not be parsed.
+    MalformedResponse,
+    /// The terminal does not support the requested operation.
+    Unsupported(&'static str),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(error)           => write!(f, "terminal I/O error: {error}"),
+            Error::NotATty             => write!(f, "not a terminal"),
+            Error::Timeout             => write!(f, "terminal did not respond in time"),
+            Error::MalformedResponse   => write!(f, "malformed response from the terminal"),
+            Error::Unsupported(action) => write!(f, "not supported by the terminal: {action}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        return Error::Io(error);
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::Io(error) => return error,
+            error => return io::Error::other(error),
+        }
+    }
+}
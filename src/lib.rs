@@ -1,67 +1,561 @@
-#[derive(Debug)]
+#![allow(clippy::needless_return)]
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub struct Pos { 
     pub x: u16, 
     pub y: u16,
 }
 
-#[derive(Debug)]
+/// Size of the terminal window, see `window_size`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WindowSize {
+    /// Size in columns and rows.
+    pub size: Pos,
+    /// Size of the text area in pixels, zero when the terminal does not report it.
+    pub pixels: Pos,
+}
+
+impl WindowSize {
+    /// Size of a single cell in pixels, if the terminal reported its pixel size.
+    pub fn cell_size(&self) -> Option<Pos> {
+        if self.pixels.x == 0 || self.pixels.y == 0 || self.size.x == 0 || self.size.y == 0 {
+            return None;
+        }
+
+        let cell = Pos {
+            x: self.pixels.x / self.size.x,
+            y: self.pixels.y / self.size.y,
+        };
+        return Some(cell);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum KeyCode {
-    // TODO(?): 
-    //     Add modifiers (Ctrl, Alt) + console events (buffer resize, mouse click)?
     Char(char),
     Enter,
     Backspace,
     Space,
+    Tab,
+    /// Shift+Tab.
+    BackTab,
+    Escape,
     ArrowUp,
     ArrowDown,
     ArrowRight,
     ArrowLeft,
+    Home,
+    End,
+    Insert,
+    Delete,
+    PageUp,
+    PageDown,
+    /// Center key of the keypad (keypad 5 with NumLock off).
+    Begin,
+    /// Function key, F1 to F20.
+    F(u8),
     Other(u64),
-    Error,
+    /// A byte from the input stream which is not part of valid UTF-8.
+    InvalidUtf8(u8),
+}
+
+/// Set of modifier keys held down together with a key.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Modifiers(u8);
+
+impl Modifiers {
+    pub const NONE:  Modifiers = Modifiers(0);
+    pub const SHIFT: Modifiers = Modifiers(1 << 0);
+    pub const ALT:   Modifiers = Modifiers(1 << 1);
+    pub const CTRL:  Modifiers = Modifiers(1 << 2);
+    pub const SUPER: Modifiers = Modifiers(1 << 3);
+
+    pub const fn is_empty(self) -> bool {
+        return self.0 == 0;
+    }
+
+    pub const fn contains(self, other: Modifiers) -> bool {
+        return self.0 & other.0 == other.0;
+    }
+
+    pub fn insert(&mut self, other: Modifiers) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: Modifiers) {
+        self.0 &= !other.0;
+    }
+}
+
+impl std::ops::BitOr for Modifiers {
+    type Output = Modifiers;
+
+    fn bitor(self, other: Modifiers) -> Modifiers {
+        return Modifiers(self.0 | other.0);
+    }
+}
+
+impl std::ops::BitOrAssign for Modifiers {
+    fn bitor_assign(&mut self, other: Modifiers) {
+        self.0 |= other.0;
+    }
+}
+
+/// Colour of text, background or underlines, see `color_fg_set`, `color_bg_set` and
+/// `color_underline_set`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Color {
+    /// Whatever the terminal uses when no colour is set.
+    #[default]
+    Default,
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    BrightBlack,
+    BrightRed,
+    BrightGreen,
+    BrightYellow,
+    BrightBlue,
+    BrightMagenta,
+    BrightCyan,
+    BrightWhite,
+    /// Entry of the 256 colour palette. The first 16 are the named colours, followed by
+    /// a 6x6x6 colour cube and 24 shades of grey.
+    Indexed(u8),
+    /// 24-bit colour.
+    Rgb { red: u8, green: u8, blue: u8 },
+}
+
+impl Color {
+    /// The named colours in palette order.
+    pub const NAMED: [Color; 16] = [
+        Color::Black,       Color::Red,       Color::Green,       Color::Yellow,
+        Color::Blue,        Color::Magenta,   Color::Cyan,        Color::White,
+        Color::BrightBlack, Color::BrightRed, Color::BrightGreen, Color::BrightYellow,
+        Color::BrightBlue,  Color::BrightMagenta, Color::BrightCyan, Color::BrightWhite,
+    ];
+
+    /// Palette index of a named or indexed colour.
+    pub fn index(self) -> Option<u8> {
+        match self {
+            Color::Indexed(index) => return Some(index),
+            Color::Default | Color::Rgb { .. } => return None,
+            named => {
+                let index = Color::NAMED.iter().position(|&color| color == named)?;
+                return Some(index as u8);
+            }
+        }
+    }
+
+    /// Closest colour a terminal with the given colour depth can show, or `None` when it
+    /// shows no colours at all. The colour commands send colours as they are given.
+    pub fn downsample(self, depth: ColorDepth) -> Option<Color> {
+        return color::downsample(self, depth);
+    }
+}
+
+/// How many colours the terminal can show, see `color_depth`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ColorDepth {
+    /// No colours at all, colour changes are not sent.
+    None = 0,
+    /// The 16 named colours.
+    Ansi16 = 1,
+    /// The 256 colour palette.
+    Indexed256 = 2,
+    /// 24-bit colours.
+    TrueColor = 3,
+}
+
+/// Decides whether colours and text attributes are sent, see `style_policy_set`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StylePolicy {
+    /// Only style output which goes to a terminal, following the NO_COLOR, CLICOLOR,
+    /// CLICOLOR_FORCE and FORCE_COLOR conventions.
+    #[default]
+    Auto = 0,
+    /// Always style output, even when it goes to a file or a pipe.
+    Always = 1,
+    /// Never style output.
+    Never = 2,
+}
+
+/// Set of text attributes, see `attributes_set` and `attributes_clear`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Attributes(u16);
+
+impl Attributes {
+    pub const NONE:             Attributes = Attributes(0);
+    pub const BOLD:             Attributes = Attributes(1 << 0);
+    pub const DIM:              Attributes = Attributes(1 << 1);
+    pub const ITALIC:           Attributes = Attributes(1 << 2);
+    pub const UNDERLINE:        Attributes = Attributes(1 << 3);
+    pub const DOUBLE_UNDERLINE: Attributes = Attributes(1 << 4);
+    pub const CURLY_UNDERLINE:  Attributes = Attributes(1 << 5);
+    pub const DOTTED_UNDERLINE: Attributes = Attributes(1 << 6);
+    pub const DASHED_UNDERLINE: Attributes = Attributes(1 << 7);
+    pub const BLINK:            Attributes = Attributes(1 << 8);
+    pub const REVERSE:          Attributes = Attributes(1 << 9);
+    pub const HIDDEN:           Attributes = Attributes(1 << 10);
+    pub const STRIKETHROUGH:    Attributes = Attributes(1 << 11);
+
+    /// All of the underline styles.
+    pub const UNDERLINES: Attributes = Attributes(0b11111 << 3);
+
+    pub const fn is_empty(self) -> bool {
+        return self.0 == 0;
+    }
+
+    pub const fn contains(self, other: Attributes) -> bool {
+        return self.0 & other.0 == other.0;
+    }
+
+    pub const fn intersects(self, other: Attributes) -> bool {
+        return self.0 & other.0 != 0;
+    }
+
+    pub fn insert(&mut self, other: Attributes) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: Attributes) {
+        self.0 &= !other.0;
+    }
 }
 
-#[cfg(all(unix))]
+impl std::ops::BitOr for Attributes {
+    type Output = Attributes;
+
+    fn bitor(self, other: Attributes) -> Attributes {
+        return Attributes(self.0 | other.0);
+    }
+}
+
+impl std::ops::BitOrAssign for Attributes {
+    fn bitor_assign(&mut self, other: Attributes) {
+        self.0 |= other.0;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    pub modifiers: Modifiers,
+}
+
+impl KeyEvent {
+    pub const fn new(code: KeyCode, modifiers: Modifiers) -> Self {
+        return Self { code, modifiers };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+    Back,
+    Forward,
+    /// No button is involved (scrolling, movement) or the terminal did not report which one.
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseKind {
+    Press,
+    Release,
+    /// Movement with a button held down.
+    Drag,
+    /// Movement without any buttons held down.
+    Move,
+    ScrollUp,
+    ScrollDown,
+    ScrollLeft,
+    ScrollRight,
+}
+
+/// Which mouse actions the terminal reports, see `mouse_enable`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseMode {
+    /// Button presses, releases and scrolling.
+    Click,
+    /// Same as `Click`, plus movement while a button is held down.
+    Drag,
+    /// Same as `Click`, plus all movement.
+    Motion,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Key(KeyEvent),
+    Mouse {
+        kind: MouseKind,
+        button: MouseButton,
+        pos: Pos,
+        modifiers: Modifiers,
+    },
+    /// The terminal was resized, carries the new size in columns and rows.
+    ///
+    /// On Unix resizes are caught with a SIGWINCH handler, installed by `terma_init` or the
+    /// first read, which takes the place of any handler the application had.
+    Resize(Pos),
+    /// Text pasted while bracketed paste is enabled, see `paste_enable`.
+    ///
+    /// Line breaks are passed through as the terminal sent them, which is usually `\r`.
+    Paste(String),
+    /// The terminal window got focused, see `focus_enable`.
+    FocusGained,
+    /// The terminal window lost focus.
+    FocusLost,
+    /// Reply to a query which arrived while nothing was waiting for it.
+    TerminalResponse(Response),
+}
+
+/// Reply of the terminal to a query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    /// Cursor position report, 0 indexed.
+    CursorPosition(Pos),
+    /// Any other reply, carries the parameter bytes and the final byte of the control sequence.
+    Other(Vec<u8>),
+    /// Device control string (`ESC P ... ESC \`), carries everything in between.
+    DeviceControl(Vec<u8>),
+}
+
+/// Part of the screen erased by `console_erase`, relative to the cursor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClearType {
+    /// From the cursor to the end of the line.
+    LineEnd,
+    /// From the start of the line up to and including the cursor.
+    LineStart,
+    /// The whole line the cursor is on.
+    Line,
+    /// From the cursor to the end of the screen.
+    ScreenEnd,
+    /// From the start of the screen up to and including the cursor.
+    ScreenStart,
+    /// The whole screen, without moving the cursor.
+    Screen,
+    /// Lines scrolled off the top of the screen, leaving the visible screen alone.
+    Scrollback,
+    /// Given number of characters starting at the cursor, without moving the rest of the line.
+    Characters(u16),
+}
+
+/// Look of the cursor, see `cursor_shape`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CursorShape {
+    /// Whatever the user configured in their terminal.
+    #[default]
+    Default,
+    BlinkingBlock,
+    SteadyBlock,
+    BlinkingUnderline,
+    SteadyUnderline,
+    BlinkingBar,
+    SteadyBar,
+}
+
+/// Keeps the cursor hidden for as long as it is alive, showing it again once dropped.
+pub struct HiddenCursor {
+    // Prevents construction without hiding the cursor.
+    _private: (),
+}
+
+impl HiddenCursor {
+    pub fn hide() -> Result<HiddenCursor> {
+        cursor_hide()?;
+        return Ok(HiddenCursor { _private: () });
+    }
+}
+
+impl Drop for HiddenCursor {
+    fn drop(&mut self) {
+        let _ = cursor_show();
+    }
+}
+
+/// Settings applied when entering raw mode with `RawMode::enter_with`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RawConfig {
+    /// Keep Ctrl+C, Ctrl+Z and `Ctrl+\` generating signals instead of delivering them as keys.
+    pub signals: bool,
+    /// Switch to the alternate screen while raw mode is active, leaving the shell output
+    /// untouched underneath. It is shown again once the guard is dropped or the program panics.
+    pub alternate_screen: bool,
+}
+
+#[cfg(unix)]
 mod unix;
 
 #[cfg(target_os = "windows")]
 mod windows;
 
 mod ansi;
+mod color;
+mod command;
+mod error;
+mod parser;
+mod terminal;
+
+pub use command::{
+    Command,
+    CursorSet,
+    CursorUp,
+    CursorDown,
+    CursorLeft,
+    CursorRight,
+    CursorLineNext,
+    CursorLinePrev,
+    CursorColumn,
+    CursorSave,
+    CursorRestore,
+    CursorHide,
+    CursorShow,
+    CursorShapeSet,
+    ConsoleClear,
+    ConsoleErase,
+    ColorReset,
+    ColorFg,
+    ColorBg,
+    ColorUnderline,
+    AttributesSet,
+    AttributesClear,
+    MouseEnable,
+    MouseDisable,
+    PasteEnable,
+    PasteDisable,
+    FocusEnable,
+    FocusDisable,
+    AlternateScreenEnter,
+    AlternateScreenLeave,
+};
+pub use error::{Error, Result};
+pub use terminal::Terminal;
 
 // TODO: 
-//   - try_read_key() - Non-blocking read_key
 //   - console_get()  - Get info about console (?)
-//   - clear_line()   - Clear line at current cursor position
-//   - clear_end()    - Clear console at current cursor position till end 
 
-#[cfg(all(unix))]
+#[cfg(unix)]
 pub use unix::{
+    RawMode,
     terma_init,
     print_str,
     print_buf,
     read_buf,
+    read_event,
+    poll_event,
     read_key,
+    read_key_event,
+    try_read_key,
+    read_key_timeout,
+    mouse_enable,
+    mouse_disable,
+    paste_enable,
+    paste_disable,
+    focus_enable,
+    focus_disable,
+    alternate_screen_enter,
+    alternate_screen_leave,
     console_clear,
+    console_erase,
     cursor_get,
+    cursor_up,
+    cursor_down,
+    cursor_left,
+    cursor_right,
+    cursor_line_next,
+    cursor_line_prev,
+    cursor_column,
+    cursor_save,
+    cursor_restore,
+    cursor_hide,
+    cursor_show,
+    cursor_shape,
     cursor_set,
     color_bg,
     color_fg,
     color_reset,
+    color_depth,
+    color_depth_set,
+    color_depth_query,
+    style_policy,
+    style_policy_set,
+    style_enabled,
+    color_underline,
+    color_fg_set,
+    color_bg_set,
+    color_underline_set,
+    attributes_set,
+    attributes_clear,
     buffer_size,
+    window_size,
 };
 
+#[cfg(unix)]
+use unix::{wait_input, resize_take, window_size_tracked, ansi_supported, Handle};
+
 #[cfg(target_os = "windows")]
 pub use windows::{
+    RawMode,
     terma_init,
     print_str,
     print_buf,
     read_buf,
+    read_event,
+    poll_event,
     read_key,
+    read_key_event,
+    try_read_key,
+    read_key_timeout,
+    mouse_enable,
+    mouse_disable,
+    paste_enable,
+    paste_disable,
+    focus_enable,
+    focus_disable,
+    alternate_screen_enter,
+    alternate_screen_leave,
     console_clear,
+    console_erase,
     cursor_get,
+    cursor_up,
+    cursor_down,
+    cursor_left,
+    cursor_right,
+    cursor_line_next,
+    cursor_line_prev,
+    cursor_column,
+    cursor_save,
+    cursor_restore,
+    cursor_hide,
+    cursor_show,
+    cursor_shape,
     cursor_set,
     color_bg,
     color_fg,
     color_reset,
+    color_depth,
+    color_depth_set,
+    color_depth_query,
+    style_policy,
+    style_policy_set,
+    style_enabled,
+    color_underline,
+    color_fg_set,
+    color_bg_set,
+    color_underline_set,
+    attributes_set,
+    attributes_clear,
     buffer_size,
+    window_size,
 };
+
+#[cfg(target_os = "windows")]
+use windows::{wait_input, resize_take, window_size_tracked, ansi_supported, Handle};
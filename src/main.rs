@@ -1,4 +1,5 @@
-use std::thread;
+#![allow(dead_code, clippy::needless_return)]
+
 use terma_utils::{
     KeyCode,
     terma_init,
@@ -6,37 +7,37 @@ use terma_utils::{
     console_clear,
     cursor_get,
     cursor_set,
-    color_fg,
     color_bg,
     color_reset,
     buffer_size,
+    Result,
 };
 
-fn console_test1() {
+fn console_test1() -> Result<()> {
     loop {
-        let size = buffer_size();
+        let size = buffer_size()?;
 
-        let value = read_key();
-        color_bg(255, 0, 0);
-        console_clear();
-        cursor_set(size.x / 2, size.y / 2);
+        let value = read_key()?;
+        color_bg(255, 0, 0)?;
+        console_clear()?;
+        cursor_set(size.x / 2, size.y / 2)?;
         println!("{value:?}");
 
-        let value = read_key();
-        color_reset();
-        console_clear();
-        cursor_set(size.x / 2, size.y / 2);
+        let value = read_key()?;
+        color_reset()?;
+        console_clear()?;
+        cursor_set(size.x / 2, size.y / 2)?;
         println!("{value:?}");
     }
 }
 
-fn console_test2() {
+fn console_test2() -> Result<()> {
     let mut x = 10u16;
     let mut y = 10u16;
 
-    //console_clear();
+    //console_clear()?;
     loop {
-        let value = read_key();
+        let value = read_key()?;
         //println!("{value:?}");
         match value {
             KeyCode::ArrowUp => y -= 1,
@@ -48,36 +49,36 @@ fn console_test2() {
 
         use std::io::Write;
 
-        console_clear();
+        console_clear()?;
 
-        cursor_set(x, y - 1);
+        cursor_set(x, y - 1)?;
         print!("   TERMA UTILS");
-        std::io::stdout().flush();
+        let _ = std::io::stdout().flush();
 
-        cursor_set(x, y);
-        print!("{:?}", cursor_get());
-        std::io::stdout().flush();
+        cursor_set(x, y)?;
+        print!("{:?}", cursor_get()?);
+        let _ = std::io::stdout().flush();
 
-        cursor_set(x, y + 1);
+        cursor_set(x, y + 1)?;
         print!("    {:?}", value);
-        std::io::stdout().flush();
+        let _ = std::io::stdout().flush();
 
-        cursor_set(x + 8, y + 2);
+        cursor_set(x + 8, y + 2)?;
     }
 }
 
-fn console_test3() {
+fn console_test3() -> Result<()> {
     loop {
-        let key = read_key();
+        let _key = read_key()?;
         // println!("{key:?}");
-        // let size = buffer_size();
+        // let size = buffer_size()?;
         // println!("{size:?}");
 
-        let pos = cursor_get();
+        let pos = cursor_get()?;
         println!("{pos:?}");
-        cursor_set(pos.x, pos.y);
+        cursor_set(pos.x, pos.y)?;
 
-        // let pos = cursor_get();
+        // let pos = cursor_get()?;
         // println!("{pos:?}");
         // use std::io::Read;
         // let mut stdin = std::io::stdin();
@@ -86,12 +87,14 @@ fn console_test3() {
     }
 }
 
-fn console_test4() { }
+fn console_test4() -> Result<()> {
+    return Ok(());
+}
 
-fn main() {
-    terma_init();
+fn main() -> Result<()> {
+    terma_init()?;
 
-    console_test1();
-    // console_test2();
-    // console_test3();
+    return console_test1();
+    // return console_test2();
+    // return console_test3();
 }
@@ -0,0 +1,916 @@
+use std::collections::VecDeque;
+use crate::{Event, KeyCode, KeyEvent, Modifiers, MouseButton, MouseKind, Pos, Response};
+
+// NOTE: Upper bound for the parameter bytes of a single control sequence.
+//       Anything longer is garbage and gets discarded.
+const MAX_PARAMS: usize = 32;
+
+// NOTE: Sent by the terminal after a bracketed paste.
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+// NOTE: Ends device control strings. Replies longer than the limit are garbage.
+const STRING_END: &[u8] = b"\x1b\\";
+const MAX_STRING: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    Csi,
+    CsiBracket,
+    X10Mouse,
+    Ss3,
+    Utf8,
+    Paste,
+    DeviceControl,
+}
+
+/// Incremental VT/xterm input decoder.
+///
+/// Bytes are fed in with `advance` as they arrive from the terminal, in chunks of any size.
+/// Sequences split across multiple reads are kept in the parser state until they complete,
+/// and every decoded event is queued until it is taken out with `next`.
+#[derive(Debug)]
+pub(crate) struct Parser {
+    state:  State,
+    params: Vec<u8>,
+    events: VecDeque<Event>,
+
+    // Set when the key being decoded was prefixed with ESC.
+    alt: bool,
+
+    // Bytes of the UTF-8 character currently being decoded.
+    utf8:        [u8; 4],
+    utf8_length: usize,
+    utf8_needed: usize,
+
+    // Raw button and coordinate bytes of an X10 mouse report.
+    mouse:        [u8; 3],
+    mouse_length: usize,
+
+    // Text of the bracketed paste being received, including the start of a possible end marker.
+    paste: Vec<u8>,
+
+    // Cursor position reports asked for which have not arrived yet, see `cursor_expect`.
+    cursor_reports: usize,
+
+    // Set while a device control string is expected, see `device_control_expect`.
+    device_control: bool,
+    device_control_data: Vec<u8>,
+}
+
+impl Parser {
+    pub(crate) const fn new() -> Self {
+        Self {
+            state:  State::Ground,
+            params: Vec::new(),
+            events: VecDeque::new(),
+
+            alt: false,
+
+            utf8:        [0; 4],
+            utf8_length: 0,
+            utf8_needed: 0,
+
+            mouse:        [0; 3],
+            mouse_length: 0,
+
+            paste: Vec::new(),
+
+            cursor_reports: 0,
+
+            device_control: false,
+            device_control_data: Vec::new(),
+        }
+    }
+
+    pub(crate) fn advance(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.advance_byte(byte);
+        }
+    }
+
+    pub(crate) fn next(&mut self) -> Option<Event> {
+        return self.events.pop_front();
+    }
+
+    /// Takes the first queued response accepted by `matches`, leaving every other event queued.
+    pub(crate) fn response_take(&mut self, matches: impl Fn(&Response) -> bool) -> Option<Response> {
+        let index = self.events.iter().position(|event| match event {
+            Event::TerminalResponse(response) => matches(response),
+            _ => false,
+        })?;
+
+        match self.events.remove(index) {
+            Some(Event::TerminalResponse(response)) => return Some(response),
+            _ => return None,
+        }
+    }
+
+    /// Tells the parser that a cursor position report is on its way, or that one asked for
+    /// is not going to be waited for any more.
+    // NOTE: A report looks exactly like F3 with modifiers (`ESC [ 1 ; 5 R`), so it is only
+    //       decoded as one while a report is expected.
+    pub(crate) fn cursor_expect(&mut self, expect: bool) {
+        if expect {
+            self.cursor_reports += 1;
+        } else {
+            self.cursor_reports = self.cursor_reports.saturating_sub(1);
+        }
+    }
+
+    /// Tells the parser whether a device control string might be on its way.
+    // NOTE: Its start, `ESC P`, is also what Alt+Shift+P sends, so it is only decoded as
+    //       a device control string while a query waits for one.
+    pub(crate) fn device_control_expect(&mut self, expect: bool) {
+        self.device_control = expect;
+
+        // An `ESC P` received after the reply was typed, decode it as keys after all.
+        if !expect && self.state == State::DeviceControl {
+            self.state = State::Ground;
+            self.alt = true;
+            self.ground(b'P');
+
+            let data = std::mem::take(&mut self.device_control_data);
+            self.advance(&data);
+        }
+    }
+
+    /// Returns true when the parser is holding an ESC which could either be a key of its own
+    /// or the start of a sequence that has not fully arrived yet.
+    pub(crate) fn escape_pending(&self) -> bool {
+        match self.state {
+            State::Escape => return true,
+            State::Csi | State::Ss3 => return self.params.is_empty(),
+            _ => return false,
+        }
+    }
+
+    /// Called by the reader once no more input follows. A pending ESC is then the Escape key,
+    /// and `ESC [` or `ESC O` without anything after them are Alt+[ and Alt+Shift+O.
+    pub(crate) fn idle(&mut self) {
+        if !self.escape_pending() {
+            return;
+        }
+
+        let state = self.state;
+        self.state = State::Ground;
+        self.alt_escape_flush();
+        match state {
+            State::Csi => {
+                self.alt = true;
+                self.ground(b'[');
+            }
+            State::Ss3 => {
+                self.alt = true;
+                self.ground(b'O');
+            }
+            _ => self.push(KeyCode::Escape, Modifiers::NONE),
+        }
+    }
+
+    fn advance_byte(&mut self, byte: u8) {
+        match self.state {
+            State::Ground     => self.ground(byte),
+            State::Escape     => self.escape(byte),
+            State::Csi        => self.csi(byte),
+            State::CsiBracket => self.csi_bracket(byte),
+            State::X10Mouse   => self.x10_mouse(byte),
+            State::Ss3        => self.ss3(byte),
+            State::Utf8       => self.utf8(byte),
+            State::Paste      => self.paste(byte),
+            State::DeviceControl => self.device_control_string(byte),
+        }
+
+        // Sequence finished without producing a key, so there is nothing to apply Alt to.
+        if self.state == State::Ground {
+            self.alt = false;
+        }
+    }
+
+    /// Emits an ESC that was held as a possible Alt prefix as the Escape key of its own.
+    fn alt_escape_flush(&mut self) {
+        if !self.alt {
+            return;
+        }
+
+        self.alt = false;
+        self.push(KeyCode::Escape, Modifiers::NONE);
+    }
+
+    fn push(&mut self, code: KeyCode, mut modifiers: Modifiers) {
+        if self.alt {
+            modifiers |= Modifiers::ALT;
+            self.alt = false;
+        }
+
+        self.events.push_back(Event::Key(KeyEvent::new(code, modifiers)));
+    }
+
+    fn ground(&mut self, byte: u8) {
+        let (code, modifiers) = match byte {
+            0x1b => {
+                self.state = State::Escape;
+                return;
+            }
+            0xc2..=0xdf => return self.utf8_start(byte, 1),
+            0xe0..=0xef => return self.utf8_start(byte, 2),
+            0xf0..=0xf4 => return self.utf8_start(byte, 3),
+            0x80..=0xff => (KeyCode::InvalidUtf8(byte), Modifiers::NONE),
+            b'\n'       => (KeyCode::Enter,             Modifiers::NONE),
+            b'\r'       => (KeyCode::Enter,             Modifiers::NONE),
+            b' '        => (KeyCode::Space,             Modifiers::NONE),
+            0x08        => (KeyCode::Backspace,         Modifiers::NONE),
+            127         => (KeyCode::Backspace,         Modifiers::NONE),
+            0x21..=0x7e => (KeyCode::Char(byte as char), Modifiers::NONE),
+            b'\t'       => (KeyCode::Tab,               Modifiers::NONE),
+
+            // C0 control codes are what the terminal sends for Ctrl+key.
+            0x00        => (KeyCode::Space,                           Modifiers::CTRL),
+            0x01..=0x1a => (KeyCode::Char((b'a' + byte - 1) as char), Modifiers::CTRL),
+            0x1c..=0x1f => (KeyCode::Char((b'4' + byte - 0x1c) as char), Modifiers::CTRL),
+        };
+
+        self.push(code, modifiers);
+    }
+
+    fn escape(&mut self, byte: u8) {
+        match byte {
+            b'[' => {
+                self.params.clear();
+                self.state = State::Csi;
+            }
+            b'O' => {
+                self.params.clear();
+                self.state = State::Ss3;
+            }
+            b'P' if self.device_control => {
+                self.device_control_data.clear();
+                self.state = State::DeviceControl;
+            }
+            // Alt+sequence as sent by rxvt, e.g. `ESC ESC [ A`. Whether the second ESC really is
+            // an Alt prefix is only known once a sequence follows it.
+            0x1b => {
+                self.alt_escape_flush();
+                self.alt = true;
+            }
+            _ => {
+                self.alt_escape_flush();
+                self.alt = true;
+                self.state = State::Ground;
+                self.ground(byte);
+            }
+        }
+    }
+
+    fn csi(&mut self, byte: u8) {
+        match byte {
+            // rxvt ends Shift+key sequences with `$`, which is otherwise an intermediate byte.
+            b'$' if !self.params.is_empty() && self.params.iter().all(u8::is_ascii_digit) => {
+                self.state = State::Ground;
+                self.dispatch_csi(byte);
+            }
+            // Parameter and intermediate bytes.
+            0x20..=0x3f => {
+                if self.params.len() >= MAX_PARAMS {
+                    self.state = State::Ground;
+                    return;
+                }
+
+                self.params.push(byte);
+            }
+            // Final byte.
+            0x40..=0x7e => {
+                self.state = State::Ground;
+                self.dispatch_csi(byte);
+            }
+            0x1b => self.state = State::Escape,
+            // Malformed sequence, drop it.
+            _ => self.state = State::Ground,
+        }
+    }
+
+    fn ss3(&mut self, byte: u8) {
+        match byte {
+            // Some terminals put the modifier parameter into SS3 sequences as well.
+            b'0'..=b'9' | b';' if self.params.len() < MAX_PARAMS => self.params.push(byte),
+            _ => {
+                self.state = State::Ground;
+                self.dispatch_ss3(byte);
+            }
+        }
+    }
+
+    fn utf8_start(&mut self, byte: u8, continuation_bytes: usize) {
+        self.utf8[0] = byte;
+        self.utf8_length = 1;
+        self.utf8_needed = continuation_bytes;
+        self.state = State::Utf8;
+    }
+
+    fn utf8(&mut self, byte: u8) {
+        // Sequence got cut short, report what was collected and start over from this byte.
+        if byte & 0xc0 != 0x80 {
+            self.utf8_invalid();
+            self.state = State::Ground;
+            self.ground(byte);
+            return;
+        }
+
+        self.utf8[self.utf8_length] = byte;
+        self.utf8_length += 1;
+        self.utf8_needed -= 1;
+
+        if self.utf8_needed > 0 {
+            return;
+        }
+
+        self.state = State::Ground;
+
+        // Rejects overlong encodings, surrogates and code points past U+10FFFF.
+        let bytes = &self.utf8[..self.utf8_length];
+        match std::str::from_utf8(bytes).ok().and_then(|string| string.chars().next()) {
+            Some(character) => self.push(KeyCode::Char(character), Modifiers::NONE),
+            None => self.utf8_invalid(),
+        }
+    }
+
+    fn utf8_invalid(&mut self) {
+        for i in 0..self.utf8_length {
+            let key = KeyEvent::new(KeyCode::InvalidUtf8(self.utf8[i]), Modifiers::NONE);
+            self.events.push_back(Event::Key(key));
+        }
+
+        self.utf8_length = 0;
+        self.utf8_needed = 0;
+    }
+
+    /// Numeric parameter at `index` of the current sequence, with missing ones read as `None`.
+    fn param(&self, index: usize) -> Option<u16> {
+        // Skip the private marker of sequences like `ESC [ < ...`.
+        let params = match self.params.first() {
+            Some(b'<'..=b'?') => &self.params[1..],
+            _ => &self.params[..],
+        };
+
+        let param = params.split(|&byte| byte == b';').nth(index)?;
+        if param.is_empty() {
+            return None;
+        }
+
+        let mut value = 0u16;
+        for &byte in param {
+            if !byte.is_ascii_digit() {
+                return None;
+            }
+
+            value = value.saturating_mul(10).saturating_add((byte - b'0') as u16);
+        }
+
+        return Some(value);
+    }
+
+    /// Modifiers encoded xterm-style as `1 + bitmask` in the parameter at `index`.
+    fn param_modifiers(&self, index: usize) -> Modifiers {
+        let mask = match self.param(index) {
+            Some(value) => value.saturating_sub(1),
+            None => 0,
+        };
+
+        let mut modifiers = Modifiers::NONE;
+        if mask & 1 != 0 { modifiers |= Modifiers::SHIFT; }
+        if mask & 2 != 0 { modifiers |= Modifiers::ALT;   }
+        if mask & 4 != 0 { modifiers |= Modifiers::CTRL;  }
+        if mask & 8 != 0 { modifiers |= Modifiers::SUPER; }
+
+        return modifiers;
+    }
+
+    fn dispatch_csi(&mut self, final_byte: u8) {
+        let (code, modifiers) = match final_byte {
+            // Replies to queries: cursor position reports, device attributes
+            // (`ESC [ ? ... c`, `ESC [ > ... c`) and mode reports (`ESC [ ? ... $ y`).
+            b'R' if self.cursor_reports > 0 && self.params.contains(&b';') => {
+                return self.dispatch_cursor_report();
+            }
+            b'c' if matches!(self.params.first(), Some(b'?' | b'>')) => return self.dispatch_response(final_byte),
+            b'y' if self.params.last() == Some(&b'$') => return self.dispatch_response(final_byte),
+
+            b'A' => (KeyCode::ArrowUp,    self.param_modifiers(1)),
+            b'B' => (KeyCode::ArrowDown,  self.param_modifiers(1)),
+            b'C' => (KeyCode::ArrowRight, self.param_modifiers(1)),
+            b'D' => (KeyCode::ArrowLeft,  self.param_modifiers(1)),
+            b'E' => (KeyCode::Begin,      self.param_modifiers(1)),
+            b'F' => (KeyCode::End,        self.param_modifiers(1)),
+            b'H' => (KeyCode::Home,       self.param_modifiers(1)),
+            b'P' => (KeyCode::F(1),       self.param_modifiers(1)),
+            b'Q' => (KeyCode::F(2),       self.param_modifiers(1)),
+            b'R' => (KeyCode::F(3),       self.param_modifiers(1)),
+            b'S' => (KeyCode::F(4),       self.param_modifiers(1)),
+            b'Z' => (KeyCode::BackTab,    Modifiers::SHIFT),
+
+            // rxvt Shift+arrow keys.
+            b'a' => (KeyCode::ArrowUp,    Modifiers::SHIFT),
+            b'b' => (KeyCode::ArrowDown,  Modifiers::SHIFT),
+            b'c' => (KeyCode::ArrowRight, Modifiers::SHIFT),
+            b'd' => (KeyCode::ArrowLeft,  Modifiers::SHIFT),
+
+            // Mouse reports.
+            b'M' if self.params.is_empty() => {
+                self.mouse_length = 0;
+                self.state = State::X10Mouse;
+                return;
+            }
+            b'M' | b'm' if self.params.first() == Some(&b'<') => return self.dispatch_sgr_mouse(final_byte),
+            b'M' => return self.dispatch_urxvt_mouse(),
+
+            // Focus reports.
+            b'I' if self.params.is_empty() => {
+                self.events.push_back(Event::FocusGained);
+                return;
+            }
+            b'O' if self.params.is_empty() => {
+                self.events.push_back(Event::FocusLost);
+                return;
+            }
+
+            // Linux console function keys, `ESC [ [ A` to `ESC [ [ E`.
+            b'[' if self.params.is_empty() => {
+                self.state = State::CsiBracket;
+                return;
+            }
+
+            // VT220 style keys. rxvt marks the modifiers with the final byte instead.
+            b'~' => return self.dispatch_tilde(self.param_modifiers(1)),
+            b'$' => return self.dispatch_tilde(Modifiers::SHIFT),
+            b'^' => return self.dispatch_tilde(Modifiers::CTRL),
+            b'@' => return self.dispatch_tilde(Modifiers::CTRL | Modifiers::SHIFT),
+            _ => return,
+        };
+
+        self.push(code, modifiers);
+    }
+
+    /// Cursor position report, `ESC [ row ; column R`.
+    fn dispatch_cursor_report(&mut self) {
+        self.cursor_reports -= 1;
+
+        // NOTE: Queued as it is, so that whoever waits for the report finds out right away.
+        let (Some(row), Some(column)) = (self.param(0), self.param(1)) else {
+            return self.dispatch_response(b'R');
+        };
+
+        // Reported coordinates are 1 indexed.
+        let pos = Pos {
+            x: column.saturating_sub(1),
+            y: row.saturating_sub(1),
+        };
+
+        self.events.push_back(Event::TerminalResponse(Response::CursorPosition(pos)));
+    }
+
+    fn dispatch_response(&mut self, final_byte: u8) {
+        let mut sequence = self.params.clone();
+        sequence.push(final_byte);
+        self.events.push_back(Event::TerminalResponse(Response::Other(sequence)));
+    }
+
+    fn dispatch_tilde(&mut self, modifiers: Modifiers) {
+        let Some(number) = self.param(0) else {
+            return;
+        };
+
+        let code = match number {
+            1 | 7   => KeyCode::Home,
+            2       => KeyCode::Insert,
+            3       => KeyCode::Delete,
+            4 | 8   => KeyCode::End,
+            5       => KeyCode::PageUp,
+            6       => KeyCode::PageDown,
+            11..=15 => KeyCode::F((number - 10) as u8),
+            17..=21 => KeyCode::F((number - 11) as u8),
+            23..=26 => KeyCode::F((number - 12) as u8),
+            28..=29 => KeyCode::F((number - 13) as u8),
+            31..=34 => KeyCode::F((number - 14) as u8),
+
+            // Start of a bracketed paste, everything up to the end marker is pasted text.
+            200 => {
+                self.paste.clear();
+                self.state = State::Paste;
+                return;
+            }
+
+            // xterm modifyOtherKeys, `ESC [ 27 ; modifiers ; code ~`.
+            27 => {
+                let Some(character) = self.param(2).and_then(|code| char::from_u32(code as u32)) else {
+                    return;
+                };
+
+                let code = match character {
+                    '\r'   => KeyCode::Enter,
+                    '\t'   => KeyCode::Tab,
+                    ' '    => KeyCode::Space,
+                    '\x1b' => KeyCode::Escape,
+                    '\x7f' => KeyCode::Backspace,
+                    _      => KeyCode::Char(character),
+                };
+
+                let modifiers = self.param_modifiers(1);
+                self.push(code, modifiers);
+                return;
+            }
+            _ => return,
+        };
+
+        self.push(code, modifiers);
+    }
+
+    fn paste(&mut self, byte: u8) {
+        self.paste.push(byte);
+        if !self.paste.ends_with(PASTE_END) {
+            return;
+        }
+
+        self.paste.truncate(self.paste.len() - PASTE_END.len());
+        self.state = State::Ground;
+
+        let text = String::from_utf8_lossy(&self.paste).into_owned();
+        self.paste.clear();
+        self.events.push_back(Event::Paste(text));
+    }
+
+    fn device_control_string(&mut self, byte: u8) {
+        self.device_control_data.push(byte);
+        if self.device_control_data.len() > MAX_STRING {
+            self.state = State::Ground;
+            return;
+        }
+
+        if !self.device_control_data.ends_with(STRING_END) {
+            return;
+        }
+
+        let length = self.device_control_data.len() - STRING_END.len();
+        self.device_control_data.truncate(length);
+        self.state = State::Ground;
+
+        let data = std::mem::take(&mut self.device_control_data);
+        self.events.push_back(Event::TerminalResponse(Response::DeviceControl(data)));
+    }
+
+    fn csi_bracket(&mut self, byte: u8) {
+        self.state = State::Ground;
+
+        let code = match byte {
+            b'A'..=b'E' => KeyCode::F(byte - b'A' + 1),
+            _ => return,
+        };
+
+        self.push(code, Modifiers::NONE);
+    }
+
+    /// SGR (1006) mouse report, `ESC [ < button ; x ; y M` or `m` on release.
+    fn dispatch_sgr_mouse(&mut self, final_byte: u8) {
+        let (Some(button), Some(x), Some(y)) = (self.param(0), self.param(1), self.param(2)) else {
+            return;
+        };
+
+        self.push_mouse(button, x, y, final_byte == b'm');
+    }
+
+    /// urxvt (1015) mouse report, `ESC [ button ; x ; y M` with the button offset by 32.
+    fn dispatch_urxvt_mouse(&mut self) {
+        let (Some(button), Some(x), Some(y)) = (self.param(0), self.param(1), self.param(2)) else {
+            return;
+        };
+
+        self.push_mouse(button.saturating_sub(32), x, y, false);
+    }
+
+    /// X10 mouse report, `ESC [ M` followed by three raw bytes, each offset by 32.
+    fn x10_mouse(&mut self, byte: u8) {
+        self.mouse[self.mouse_length] = byte;
+        self.mouse_length += 1;
+
+        if self.mouse_length < self.mouse.len() {
+            return;
+        }
+
+        self.state = State::Ground;
+
+        let [button, x, y] = self.mouse.map(|byte| byte.saturating_sub(32) as u16);
+        self.push_mouse(button, x, y, false);
+    }
+
+    /// Decodes the xterm button code shared by all of the mouse report encodings.
+    fn push_mouse(&mut self, code: u16, x: u16, y: u16, release: bool) {
+        let mut modifiers = Modifiers::NONE;
+        if code & 4  != 0 { modifiers |= Modifiers::SHIFT; }
+        if code & 8  != 0 { modifiers |= Modifiers::ALT;   }
+        if code & 16 != 0 { modifiers |= Modifiers::CTRL;  }
+
+        let wheel  = code & 64  != 0;
+        let extra  = code & 128 != 0;
+        let motion = code & 32  != 0;
+
+        let (kind, button) = if wheel && !extra {
+            let kind = match code & 3 {
+                0 => MouseKind::ScrollUp,
+                1 => MouseKind::ScrollDown,
+                2 => MouseKind::ScrollLeft,
+                _ => MouseKind::ScrollRight,
+            };
+
+            (kind, MouseButton::None)
+        } else {
+            let button = match (extra, code & 3) {
+                (false, 0) => MouseButton::Left,
+                (false, 1) => MouseButton::Middle,
+                (false, 2) => MouseButton::Right,
+                (true,  0) => MouseButton::Back,
+                (true,  1) => MouseButton::Forward,
+                _          => MouseButton::None,
+            };
+
+            // NOTE: Without SGR reporting a release does not say which button it was, and is
+            //       sent as button 3 instead.
+            let kind = match (release, motion, button) {
+                (true,  _,     _)                 => MouseKind::Release,
+                (false, true,  MouseButton::None) => MouseKind::Move,
+                (false, true,  _)                 => MouseKind::Drag,
+                (false, false, MouseButton::None) => MouseKind::Release,
+                (false, false, _)                 => MouseKind::Press,
+            };
+
+            (kind, button)
+        };
+
+        // Reported coordinates are 1 indexed.
+        let pos = Pos {
+            x: x.saturating_sub(1),
+            y: y.saturating_sub(1),
+        };
+
+        self.events.push_back(Event::Mouse { kind, button, pos, modifiers });
+    }
+
+    fn dispatch_ss3(&mut self, final_byte: u8) {
+        let code = match final_byte {
+            b'A' => KeyCode::ArrowUp,
+            b'B' => KeyCode::ArrowDown,
+            b'C' => KeyCode::ArrowRight,
+            b'D' => KeyCode::ArrowLeft,
+            b'E' => KeyCode::Begin,
+            b'F' => KeyCode::End,
+            b'H' => KeyCode::Home,
+            b'P' => KeyCode::F(1),
+            b'Q' => KeyCode::F(2),
+            b'R' => KeyCode::F(3),
+            b'S' => KeyCode::F(4),
+
+            // rxvt Ctrl+arrow keys.
+            b'a' => return self.push(KeyCode::ArrowUp,    Modifiers::CTRL),
+            b'b' => return self.push(KeyCode::ArrowDown,  Modifiers::CTRL),
+            b'c' => return self.push(KeyCode::ArrowRight, Modifiers::CTRL),
+            b'd' => return self.push(KeyCode::ArrowLeft,  Modifiers::CTRL),
+
+            // Application keypad mode.
+            b'M' => KeyCode::Enter,
+            b'X' => KeyCode::Char('='),
+            b'j' => KeyCode::Char('*'),
+            b'k' => KeyCode::Char('+'),
+            b'l' => KeyCode::Char(','),
+            b'm' => KeyCode::Char('-'),
+            b'n' => KeyCode::Char('.'),
+            b'o' => KeyCode::Char('/'),
+            b'p'..=b'y' => KeyCode::Char((final_byte - b'p' + b'0') as char),
+            _ => return,
+        };
+
+        // Either `ESC O 5 A` or `ESC O 1 ; 5 A`, depending on the terminal.
+        let modifiers = match self.params.contains(&b';') {
+            true  => self.param_modifiers(1),
+            false => self.param_modifiers(0),
+        };
+
+        self.push(code, modifiers);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds every chunk with its own `advance` call and collects the decoded events.
+    fn events(chunks: &[&[u8]]) -> Vec<Event> {
+        let mut parser = Parser::new();
+        for chunk in chunks {
+            parser.advance(chunk);
+        }
+
+        return std::iter::from_fn(|| parser.next()).collect();
+    }
+
+    /// Same as `events`, with the reader going idle after the last chunk.
+    fn events_idle(chunks: &[&[u8]]) -> Vec<Event> {
+        let mut parser = Parser::new();
+        for chunk in chunks {
+            parser.advance(chunk);
+        }
+
+        assert!(parser.escape_pending());
+        parser.idle();
+        return std::iter::from_fn(|| parser.next()).collect();
+    }
+
+    fn key(code: KeyCode, modifiers: Modifiers) -> Event {
+        return Event::Key(KeyEvent::new(code, modifiers));
+    }
+
+    fn mouse(kind: MouseKind, button: MouseButton, x: u16, y: u16, modifiers: Modifiers) -> Event {
+        return Event::Mouse { kind, button, pos: Pos { x, y }, modifiers };
+    }
+
+    #[test]
+    fn plain_keys() {
+        assert_eq!(events(&[b"a\r\x7f\t "]), [
+            key(KeyCode::Char('a'), Modifiers::NONE),
+            key(KeyCode::Enter,     Modifiers::NONE),
+            key(KeyCode::Backspace, Modifiers::NONE),
+            key(KeyCode::Tab,       Modifiers::NONE),
+            key(KeyCode::Space,     Modifiers::NONE),
+        ]);
+    }
+
+    #[test]
+    fn sequence_split() {
+        assert_eq!(events(&[b"\x1b", b"[", b"1;5", b"A"]), [key(KeyCode::ArrowUp, Modifiers::CTRL)]);
+        assert_eq!(events(&[b"x\x1b[1", b";2Bx"]), [
+            key(KeyCode::Char('x'), Modifiers::NONE),
+            key(KeyCode::ArrowDown, Modifiers::SHIFT),
+            key(KeyCode::Char('x'), Modifiers::NONE),
+        ]);
+    }
+
+    #[test]
+    fn escape_idle() {
+        let mut parser = Parser::new();
+        parser.advance(b"\x1b");
+        assert!(parser.escape_pending());
+        assert_eq!(parser.next(), None);
+
+        parser.idle();
+        assert!(!parser.escape_pending());
+        assert_eq!(parser.next(), Some(key(KeyCode::Escape, Modifiers::NONE)));
+    }
+
+    #[test]
+    fn escape_double() {
+        assert_eq!(events_idle(&[b"\x1b\x1b"]), [
+            key(KeyCode::Escape, Modifiers::NONE),
+            key(KeyCode::Escape, Modifiers::NONE),
+        ]);
+        assert_eq!(events(&[b"\x1b\x1bx"]), [
+            key(KeyCode::Escape,    Modifiers::NONE),
+            key(KeyCode::Char('x'), Modifiers::ALT),
+        ]);
+        assert_eq!(events(&[b"\x1b\x1b[A"]), [key(KeyCode::ArrowUp, Modifiers::ALT)]);
+    }
+
+    #[test]
+    fn escape_introducer_idle() {
+        assert_eq!(events_idle(&[b"\x1b["]), [key(KeyCode::Char('['), Modifiers::ALT)]);
+        assert_eq!(events_idle(&[b"\x1bO"]), [key(KeyCode::Char('O'), Modifiers::ALT)]);
+    }
+
+    #[test]
+    fn utf8_split() {
+        assert_eq!(events(&[b"\xc3", b"\xa9"]), [key(KeyCode::Char('é'), Modifiers::NONE)]);
+        assert_eq!(events(&[b"\xe2", b"\x82", b"\xac"]), [key(KeyCode::Char('€'), Modifiers::NONE)]);
+        assert_eq!(events(&[b"\xf0\x9f", b"\x98\x80"]), [key(KeyCode::Char('😀'), Modifiers::NONE)]);
+    }
+
+    #[test]
+    fn utf8_invalid() {
+        assert_eq!(events(&[b"\xff"]), [key(KeyCode::InvalidUtf8(0xff), Modifiers::NONE)]);
+
+        // Cut short by a byte which does not continue the character.
+        assert_eq!(events(&[b"\xc3a"]), [
+            key(KeyCode::InvalidUtf8(0xc3), Modifiers::NONE),
+            key(KeyCode::Char('a'),         Modifiers::NONE),
+        ]);
+
+        // Overlong encoding of `/`.
+        assert_eq!(events(&[b"\xe0\x80\xaf"]), [
+            key(KeyCode::InvalidUtf8(0xe0), Modifiers::NONE),
+            key(KeyCode::InvalidUtf8(0x80), Modifiers::NONE),
+            key(KeyCode::InvalidUtf8(0xaf), Modifiers::NONE),
+        ]);
+    }
+
+    #[test]
+    fn modifiers() {
+        assert_eq!(events(&[b"\x01"]),        [key(KeyCode::Char('a'),  Modifiers::CTRL)]);
+        assert_eq!(events(&[b"\x00"]),        [key(KeyCode::Space,      Modifiers::CTRL)]);
+        assert_eq!(events(&[b"\x1bx"]),       [key(KeyCode::Char('x'),  Modifiers::ALT)]);
+        assert_eq!(events(&[b"\x1b[1;2D"]),   [key(KeyCode::ArrowLeft,  Modifiers::SHIFT)]);
+        assert_eq!(events(&[b"\x1b[1;3C"]),   [key(KeyCode::ArrowRight, Modifiers::ALT)]);
+        assert_eq!(events(&[b"\x1b[1;8H"]),   [key(KeyCode::Home,       Modifiers::SHIFT | Modifiers::ALT | Modifiers::CTRL)]);
+        assert_eq!(events(&[b"\x1b[1;9F"]),   [key(KeyCode::End,        Modifiers::SUPER)]);
+        assert_eq!(events(&[b"\x1b[Z"]),      [key(KeyCode::BackTab,    Modifiers::SHIFT)]);
+        assert_eq!(events(&[b"\x1b[27;5;13~"]), [key(KeyCode::Enter,    Modifiers::CTRL)]);
+    }
+
+    #[test]
+    fn ss3_keys() {
+        assert_eq!(events(&[b"\x1bOP"]),     [key(KeyCode::F(1),      Modifiers::NONE)]);
+        assert_eq!(events(&[b"\x1bOA"]),     [key(KeyCode::ArrowUp,   Modifiers::NONE)]);
+        assert_eq!(events(&[b"\x1bO5A"]),    [key(KeyCode::ArrowUp,   Modifiers::CTRL)]);
+        assert_eq!(events(&[b"\x1bO1;2S"]),  [key(KeyCode::F(4),      Modifiers::SHIFT)]);
+        assert_eq!(events(&[b"\x1bOp"]),     [key(KeyCode::Char('0'), Modifiers::NONE)]);
+        assert_eq!(events(&[b"\x1bOM"]),     [key(KeyCode::Enter,     Modifiers::NONE)]);
+    }
+
+    #[test]
+    fn tilde_keys() {
+        assert_eq!(events(&[b"\x1b[3~"]),    [key(KeyCode::Delete,   Modifiers::NONE)]);
+        assert_eq!(events(&[b"\x1b[2~"]),    [key(KeyCode::Insert,   Modifiers::NONE)]);
+        assert_eq!(events(&[b"\x1b[5;5~"]),  [key(KeyCode::PageUp,   Modifiers::CTRL)]);
+        assert_eq!(events(&[b"\x1b[15~"]),   [key(KeyCode::F(5),     Modifiers::NONE)]);
+        assert_eq!(events(&[b"\x1b[24;2~"]), [key(KeyCode::F(12),    Modifiers::SHIFT)]);
+    }
+
+    #[test]
+    fn rxvt_keys() {
+        assert_eq!(events(&[b"\x1b[3$"]),  [key(KeyCode::Delete,    Modifiers::SHIFT)]);
+        assert_eq!(events(&[b"\x1b[6^"]),  [key(KeyCode::PageDown,  Modifiers::CTRL)]);
+        assert_eq!(events(&[b"\x1b[7@"]),  [key(KeyCode::Home,      Modifiers::CTRL | Modifiers::SHIFT)]);
+        assert_eq!(events(&[b"\x1b[a"]),   [key(KeyCode::ArrowUp,   Modifiers::SHIFT)]);
+        assert_eq!(events(&[b"\x1bOd"]),   [key(KeyCode::ArrowLeft, Modifiers::CTRL)]);
+        assert_eq!(events(&[b"\x1b[[A"]),  [key(KeyCode::F(1),      Modifiers::NONE)]);
+    }
+
+    #[test]
+    fn mouse_sgr() {
+        assert_eq!(events(&[b"\x1b[<0;10;5M"]),  [mouse(MouseKind::Press,      MouseButton::Left,  9, 4, Modifiers::NONE)]);
+        assert_eq!(events(&[b"\x1b[<0;10;5m"]),  [mouse(MouseKind::Release,    MouseButton::Left,  9, 4, Modifiers::NONE)]);
+        assert_eq!(events(&[b"\x1b[<18;1;1M"]),  [mouse(MouseKind::Press,      MouseButton::Right, 0, 0, Modifiers::CTRL)]);
+        assert_eq!(events(&[b"\x1b[<32;3;4M"]),  [mouse(MouseKind::Drag,       MouseButton::Left,  2, 3, Modifiers::NONE)]);
+        assert_eq!(events(&[b"\x1b[<35;3;4M"]),  [mouse(MouseKind::Move,       MouseButton::None,  2, 3, Modifiers::NONE)]);
+        assert_eq!(events(&[b"\x1b[<65;1;1M"]),  [mouse(MouseKind::ScrollDown, MouseButton::None,  0, 0, Modifiers::NONE)]);
+        assert_eq!(events(&[b"\x1b[<128;1;1M"]), [mouse(MouseKind::Press,      MouseButton::Back,  0, 0, Modifiers::NONE)]);
+    }
+
+    #[test]
+    fn mouse_x10() {
+        assert_eq!(events(&[b"\x1b[M", b" *", b"%"]), [mouse(MouseKind::Press, MouseButton::Left, 9, 4, Modifiers::NONE)]);
+        assert_eq!(events(&[b"\x1b[M#*%"]), [mouse(MouseKind::Release, MouseButton::None, 9, 4, Modifiers::NONE)]);
+        assert_eq!(events(&[b"\x1b[M%!!"]), [mouse(MouseKind::Press, MouseButton::Middle, 0, 0, Modifiers::SHIFT)]);
+    }
+
+    #[test]
+    fn mouse_urxvt() {
+        assert_eq!(events(&[b"\x1b[32;10;5M"]), [mouse(MouseKind::Press,    MouseButton::Left, 9, 4, Modifiers::NONE)]);
+        assert_eq!(events(&[b"\x1b[96;1;1M"]),  [mouse(MouseKind::ScrollUp, MouseButton::None, 0, 0, Modifiers::NONE)]);
+    }
+
+    #[test]
+    fn paste() {
+        assert_eq!(events(&[b"\x1b[200~hello\r\x1b[Aworld\x1b[201~"]), [Event::Paste("hello\r\x1b[Aworld".to_string())]);
+
+        // End marker split across reads, with a key right after it.
+        assert_eq!(events(&[b"\x1b[200~h\xc3", b"\xa9\x1b[20", b"1~x"]), [
+            Event::Paste("hé".to_string()),
+            key(KeyCode::Char('x'), Modifiers::NONE),
+        ]);
+    }
+
+    #[test]
+    fn focus() {
+        assert_eq!(events(&[b"\x1b[I\x1b[O"]), [Event::FocusGained, Event::FocusLost]);
+    }
+
+    #[test]
+    fn cursor_report() {
+        // Without a query in flight, this is F3 with modifiers.
+        assert_eq!(events(&[b"\x1b[1;5R"]), [key(KeyCode::F(3), Modifiers::CTRL)]);
+
+        let mut parser = Parser::new();
+        parser.cursor_expect(true);
+        parser.advance(b"\x1b[5;10R\x1b[1;5R");
+        assert_eq!(parser.next(), Some(Event::TerminalResponse(Response::CursorPosition(Pos { x: 9, y: 4 }))));
+        assert_eq!(parser.next(), Some(key(KeyCode::F(3), Modifiers::CTRL)));
+        assert_eq!(parser.next(), None);
+
+        // A report without a row or column is kept as it is.
+        parser.cursor_expect(true);
+        parser.advance(b"\x1b[;R");
+        assert_eq!(parser.next(), Some(Event::TerminalResponse(Response::Other(b";R".to_vec()))));
+
+        // A query which timed out no longer claims the next report.
+        parser.cursor_expect(true);
+        parser.cursor_expect(false);
+        parser.advance(b"\x1b[1;5R");
+        assert_eq!(parser.next(), Some(key(KeyCode::F(3), Modifiers::CTRL)));
+    }
+}
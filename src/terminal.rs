@@ -0,0 +1,392 @@
+use std::fmt;
+use std::io;
+use std::time::Duration;
+use crate::{ansi, ansi_supported, color, Handle};
+use crate::{Attributes, ClearType, Color, ColorDepth, CursorShape, Error, Event, KeyCode, KeyEvent, MouseMode, Pos, RawConfig, Result, WindowSize};
+
+/// A terminal to draw on and read input from, with the same operations as the functions of
+/// the crate. `Terminal::new` uses the standard streams, on Unix it can also be opened on the
+/// controlling terminal or any pair of file descriptors, like the master side of a PTY.
+///
+/// Text and escape sequences are queued up and sent in a single write by `flush`, which also
+/// happens on drop. Input read through a terminal is queued separately from the input read by
+/// the functions of the crate.
+pub struct Terminal {
+    handle: Handle,
+    buffer: Vec<u8>,
+    // NOTE: What `StylePolicy::Auto` comes down to, `None` when it follows the standard output.
+    style_auto: Option<bool>,
+    // Set while the alternate screen is shown or the cursor shape is changed, so that both can
+    // be restored on drop.
+    alternate_screen: bool,
+    cursor_shape: bool,
+}
+
+impl Default for Terminal {
+    fn default() -> Self {
+        return Terminal::new();
+    }
+}
+
+impl Terminal {
+    /// Terminal on the standard input and output.
+    pub fn new() -> Terminal {
+        return Terminal::with_handle(Handle::stdio());
+    }
+
+    /// Opens the controlling terminal, so that a UI can be drawn even when the standard streams
+    /// are redirected.
+    #[cfg(unix)]
+    pub fn open() -> Result<Terminal> {
+        return Ok(Terminal::with_handle(Handle::tty_open()?));
+    }
+
+    /// Terminal reading from `input` and writing to `output`. Both have to stay open for as
+    /// long as the terminal is used.
+    #[cfg(unix)]
+    pub fn from_fds(input: std::os::fd::RawFd, output: std::os::fd::RawFd) -> Terminal {
+        return Terminal::with_handle(Handle::from_fds(input, output));
+    }
+
+    /// Terminal reading from and writing to the same descriptor, like the master side of a PTY.
+    #[cfg(unix)]
+    pub fn from_fd(fd: std::os::fd::RawFd) -> Terminal {
+        return Terminal::from_fds(fd, fd);
+    }
+
+    fn with_handle(handle: Handle) -> Terminal {
+        let style_auto = handle.style_auto();
+        return Terminal {
+            handle,
+            buffer: Vec::with_capacity(4096),
+            style_auto,
+            alternate_screen: false,
+            cursor_shape: false,
+        };
+    }
+
+    /// Output queued up since the last flush.
+    pub fn buffer(&self) -> &[u8] {
+        return &self.buffer;
+    }
+
+    /// Drops the queued output without sending it.
+    pub fn discard(&mut self) {
+        self.buffer.clear();
+    }
+
+    /// Queues a sequence, or on consoles without escape sequences flushes and runs `legacy`
+    /// right away so that the order of text and console changes is kept.
+    fn queue(
+        &mut self,
+        sequence: impl FnOnce(&mut Vec<u8>) -> Result<()>,
+        legacy: impl FnOnce() -> Result<()>,
+    ) -> Result<()> {
+        if ansi_supported() {
+            return sequence(&mut self.buffer);
+        }
+
+        self.buffer_flush()?;
+        return legacy();
+    }
+
+    fn buffer_flush(&mut self) -> Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        // NOTE: Cleared even when the write fails, a partly sent frame can not be resumed.
+        let result = self.handle.write(&self.buffer);
+        self.buffer.clear();
+        return result;
+    }
+
+    /// Switches the terminal into raw mode, see `RawMode`. It is left again on drop.
+    pub fn raw_mode_enter(&mut self, config: RawConfig) -> Result<()> {
+        self.handle.raw_mode_enter(config)?;
+        if !config.alternate_screen {
+            return Ok(());
+        }
+
+        // NOTE: Half entered raw mode is left again, same as `RawMode::enter_with` does.
+        let result = self.alternate_screen_enter().and_then(|_| self.buffer_flush());
+        if result.is_err() {
+            let _ = self.handle.raw_mode_leave();
+        }
+
+        return result;
+    }
+
+    /// Restores the terminal settings from before `raw_mode_enter`, leaving the alternate
+    /// screen and resetting the cursor shape like dropping a `RawMode` guard does.
+    pub fn raw_mode_leave(&mut self) -> Result<()> {
+        // NOTE: The terminal settings are restored even when the screen could not be, the
+        //       first error is returned.
+        let restored = self.restore();
+        let left = self.handle.raw_mode_leave();
+        return restored.and(left);
+    }
+
+    fn restore(&mut self) -> Result<()> {
+        if self.alternate_screen {
+            self.alternate_screen_leave()?;
+        }
+
+        if self.cursor_shape {
+            self.cursor_shape(CursorShape::Default)?;
+        }
+
+        return self.buffer_flush();
+    }
+
+    /// Returns true when colours and text attributes are sent to this terminal.
+    pub fn style_enabled(&self) -> bool {
+        match self.style_auto {
+            Some(auto) => return color::style_enabled_with(auto),
+            None => return color::style_enabled(),
+        }
+    }
+
+    pub fn print_str(&mut self, string: &str) -> Result<()> {
+        self.buffer.extend_from_slice(string.as_bytes());
+        return Ok(());
+    }
+
+    /// Reads raw input, same as `read_buf`. Input the event readers took in already but did
+    /// not decode yet is not included.
+    pub fn read_buf(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        return self.handle.read_buf(buffer);
+    }
+
+    pub fn read_event(&mut self) -> Result<Event> {
+        let event = self.handle.read_event(None)?;
+
+        // NOTE: Without a timeout the reader only returns once it has an event.
+        return event.ok_or(Error::Timeout);
+    }
+
+    pub fn poll_event(&mut self, timeout: Duration) -> Result<Option<Event>> {
+        return self.handle.read_event(Some(timeout));
+    }
+
+    pub fn read_key(&mut self) -> Result<KeyCode> {
+        return Ok(self.read_key_event()?.code);
+    }
+
+    pub fn read_key_event(&mut self) -> Result<KeyEvent> {
+        let event = self.handle.read_key_event(None)?;
+
+        // NOTE: Without a timeout the reader only returns once it has a key.
+        return event.ok_or(Error::Timeout);
+    }
+
+    pub fn try_read_key(&mut self) -> Result<Option<KeyCode>> {
+        return self.read_key_timeout(Duration::ZERO);
+    }
+
+    pub fn read_key_timeout(&mut self, timeout: Duration) -> Result<Option<KeyCode>> {
+        let event = self.handle.read_key_event(Some(timeout))?;
+        return Ok(event.map(|event| event.code));
+    }
+
+    /// Asks the terminal where the cursor is, sending the queued output first.
+    pub fn cursor_get(&mut self) -> Result<Pos> {
+        self.buffer_flush()?;
+        return self.handle.cursor_get();
+    }
+
+    pub fn cursor_set(&mut self, x: u16, y: u16) -> Result<()> {
+        return self.queue(|out| ansi::cursor_set(out, x, y), || crate::cursor_set(x, y));
+    }
+
+    pub fn cursor_up(&mut self, count: u16) -> Result<()> {
+        return self.queue(|out| ansi::cursor_up(out, count), || crate::cursor_up(count));
+    }
+
+    pub fn cursor_down(&mut self, count: u16) -> Result<()> {
+        return self.queue(|out| ansi::cursor_down(out, count), || crate::cursor_down(count));
+    }
+
+    pub fn cursor_left(&mut self, count: u16) -> Result<()> {
+        return self.queue(|out| ansi::cursor_left(out, count), || crate::cursor_left(count));
+    }
+
+    pub fn cursor_right(&mut self, count: u16) -> Result<()> {
+        return self.queue(|out| ansi::cursor_right(out, count), || crate::cursor_right(count));
+    }
+
+    pub fn cursor_line_next(&mut self, count: u16) -> Result<()> {
+        return self.queue(|out| ansi::cursor_line_next(out, count), || crate::cursor_line_next(count));
+    }
+
+    pub fn cursor_line_prev(&mut self, count: u16) -> Result<()> {
+        return self.queue(|out| ansi::cursor_line_prev(out, count), || crate::cursor_line_prev(count));
+    }
+
+    pub fn cursor_column(&mut self, x: u16) -> Result<()> {
+        return self.queue(|out| ansi::cursor_column(out, x), || crate::cursor_column(x));
+    }
+
+    pub fn cursor_save(&mut self) -> Result<()> {
+        return self.queue(ansi::cursor_save, crate::cursor_save);
+    }
+
+    pub fn cursor_restore(&mut self) -> Result<()> {
+        return self.queue(ansi::cursor_restore, crate::cursor_restore);
+    }
+
+    pub fn cursor_hide(&mut self) -> Result<()> {
+        return self.queue(ansi::cursor_hide, crate::cursor_hide);
+    }
+
+    pub fn cursor_show(&mut self) -> Result<()> {
+        return self.queue(ansi::cursor_show, crate::cursor_show);
+    }
+
+    /// Changes the look of the cursor, it is reset on drop.
+    pub fn cursor_shape(&mut self, shape: CursorShape) -> Result<()> {
+        self.queue(|out| ansi::cursor_shape_write(out, shape), || crate::cursor_shape(shape))?;
+        self.cursor_shape = shape != CursorShape::Default;
+        return Ok(());
+    }
+
+    pub fn mouse_enable(&mut self, mode: MouseMode) -> Result<()> {
+        return self.queue(|out| ansi::mouse_enable(out, mode), || crate::mouse_enable(mode));
+    }
+
+    pub fn mouse_disable(&mut self) -> Result<()> {
+        return self.queue(ansi::mouse_disable, crate::mouse_disable);
+    }
+
+    pub fn paste_enable(&mut self) -> Result<()> {
+        return self.queue(ansi::paste_enable, crate::paste_enable);
+    }
+
+    pub fn paste_disable(&mut self) -> Result<()> {
+        return self.queue(ansi::paste_disable, crate::paste_disable);
+    }
+
+    pub fn focus_enable(&mut self) -> Result<()> {
+        return self.queue(ansi::focus_enable, crate::focus_enable);
+    }
+
+    pub fn focus_disable(&mut self) -> Result<()> {
+        return self.queue(ansi::focus_disable, crate::focus_disable);
+    }
+
+    /// Switches to the alternate screen, it is left again on drop.
+    pub fn alternate_screen_enter(&mut self) -> Result<()> {
+        self.queue(|out| ansi::alternate_screen_write(out, true), crate::alternate_screen_enter)?;
+        self.alternate_screen = true;
+        return Ok(());
+    }
+
+    pub fn alternate_screen_leave(&mut self) -> Result<()> {
+        self.alternate_screen = false;
+        return self.queue(|out| ansi::alternate_screen_write(out, false), crate::alternate_screen_leave);
+    }
+
+    pub fn console_clear(&mut self) -> Result<()> {
+        return self.queue(ansi::console_clear, crate::console_clear);
+    }
+
+    pub fn console_erase(&mut self, clear_type: ClearType) -> Result<()> {
+        return self.queue(|out| ansi::console_erase(out, clear_type), || crate::console_erase(clear_type));
+    }
+
+    pub fn color_reset(&mut self) -> Result<()> {
+        let styled = self.style_enabled();
+        return self.queue(|out| ansi::color_reset(out, styled), crate::color_reset);
+    }
+
+    pub fn color_bg(&mut self, red: u8, green: u8, blue: u8) -> Result<()> {
+        let styled = self.style_enabled();
+        return self.queue(|out| ansi::color_bg(out, styled, red, green, blue), || crate::color_bg(red, green, blue));
+    }
+
+    pub fn color_fg(&mut self, red: u8, green: u8, blue: u8) -> Result<()> {
+        let styled = self.style_enabled();
+        return self.queue(|out| ansi::color_fg(out, styled, red, green, blue), || crate::color_fg(red, green, blue));
+    }
+
+    pub fn color_underline(&mut self, red: u8, green: u8, blue: u8) -> Result<()> {
+        let styled = self.style_enabled();
+        return self.queue(
+            |out| ansi::color_underline(out, styled, red, green, blue),
+            || crate::color_underline(red, green, blue),
+        );
+    }
+
+    pub fn color_fg_set(&mut self, color: Color) -> Result<()> {
+        let styled = self.style_enabled();
+        return self.queue(|out| ansi::color_fg_set(out, styled, color), || crate::color_fg_set(color));
+    }
+
+    pub fn color_bg_set(&mut self, color: Color) -> Result<()> {
+        let styled = self.style_enabled();
+        return self.queue(|out| ansi::color_bg_set(out, styled, color), || crate::color_bg_set(color));
+    }
+
+    pub fn color_underline_set(&mut self, color: Color) -> Result<()> {
+        let styled = self.style_enabled();
+        return self.queue(|out| ansi::color_underline_set(out, styled, color), || crate::color_underline_set(color));
+    }
+
+    /// Asks the terminal whether it shows 24-bit colours, see `color_depth_query`.
+    pub fn color_depth_query(&mut self) -> Result<ColorDepth> {
+        self.buffer_flush()?;
+        return self.handle.color_depth_query();
+    }
+
+    pub fn attributes_set(&mut self, attributes: Attributes) -> Result<()> {
+        let styled = self.style_enabled();
+        return self.queue(|out| ansi::attributes_set(out, styled, attributes), || crate::attributes_set(attributes));
+    }
+
+    pub fn attributes_clear(&mut self, attributes: Attributes) -> Result<()> {
+        let styled = self.style_enabled();
+        return self.queue(|out| ansi::attributes_clear(out, styled, attributes), || crate::attributes_clear(attributes));
+    }
+
+    pub fn buffer_size(&mut self) -> Result<Pos> {
+        return Ok(self.window_size()?.size);
+    }
+
+    pub fn window_size(&mut self) -> Result<WindowSize> {
+        // NOTE: Sending the queued output first, the size might have to be asked for.
+        self.buffer_flush()?;
+        return self.handle.window_size();
+    }
+}
+
+impl io::Write for Terminal {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buffer);
+        return Ok(buffer.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.buffer_flush()?;
+        return Ok(());
+    }
+}
+
+impl io::Read for Terminal {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        return Ok(self.read_buf(buffer)?);
+    }
+}
+
+impl fmt::Write for Terminal {
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        self.buffer.extend_from_slice(string.as_bytes());
+        return Ok(());
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        // NOTE: Raw mode itself is left when the handle is dropped right after.
+        let _ = self.restore();
+    }
+}
@@ -1,32 +1,251 @@
+use crate::Attributes;
+use crate::ClearType;
+use crate::Color;
+use crate::ColorDepth;
+use crate::CursorShape;
 use crate::KeyCode;
+use crate::KeyEvent;
+use crate::Error;
+use crate::Event;
+use crate::MouseMode;
+use crate::Result;
 use crate::Pos;
+use crate::RawConfig;
+use crate::StylePolicy;
+use crate::WindowSize;
 use crate::ansi;
+use crate::color;
+use crate::ansi::{Device, Wake};
+use crate::parser::Parser;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::panic;
+use std::sync::{Mutex, Once, TryLockError};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::time::{Duration, Instant};
 
 const STDIN:  i32 = 0;
-const STDOUT: i32 = 0;
+const STDOUT: i32 = 1;
 
 #[allow(non_camel_case_types)]
 type void = std::ffi::c_void;
 
+// NOTE: The termios layout, flag values, ioctl numbers and signal numbers below are the ones
+//       of Linux on the common architectures and of the BSDs. Linux on alpha, mips, powerpc
+//       and sparc, as well as other systems, use different values which would compile fine
+//       and then silently misbehave.
+#[cfg(not(any(
+    all(
+        any(target_os = "linux", target_os = "android"),
+        any(
+            target_arch = "x86",
+            target_arch = "x86_64",
+            target_arch = "arm",
+            target_arch = "aarch64",
+            target_arch = "riscv64",
+            target_arch = "loongarch64",
+        ),
+    ),
+    target_vendor = "apple",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd",
+)))]
+compile_error!("the termios definitions have not been verified for this target");
+
+/* termios types:
+    tcflag_t -> u32 (Linux, BSD), c_ulong (macOS)
+    cc_t     -> u8
+    speed_t  -> same as tcflag_t
+*/
+
+#[cfg(not(target_vendor = "apple"))]
+#[allow(non_camel_case_types)]
+type tcflag_t = u32;
+
+#[cfg(target_vendor = "apple")]
+#[allow(non_camel_case_types)]
+type tcflag_t = std::ffi::c_ulong;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod flags {
+    use super::tcflag_t;
+
+    pub const NCCS: usize = 32;
+
+    // Input flags.
+    pub const IGNBRK: tcflag_t = 0o000001;
+    pub const BRKINT: tcflag_t = 0o000002;
+    pub const PARMRK: tcflag_t = 0o000010;
+    pub const ISTRIP: tcflag_t = 0o000040;
+    pub const INLCR:  tcflag_t = 0o000100;
+    pub const IGNCR:  tcflag_t = 0o000200;
+    pub const ICRNL:  tcflag_t = 0o000400;
+    pub const IXON:   tcflag_t = 0o002000;
+
+    // Output flags.
+    pub const OPOST: tcflag_t = 0o000001;
+
+    // Control flags.
+    pub const CSIZE:  tcflag_t = 0o000060;
+    pub const CS8:    tcflag_t = 0o000060;
+    pub const PARENB: tcflag_t = 0o000400;
+
+    // Local flags.
+    pub const ISIG:   tcflag_t = 0o000001;
+    pub const ICANON: tcflag_t = 0o000002;
+    pub const ECHO:   tcflag_t = 0o000010;
+    pub const ECHONL: tcflag_t = 0o000100;
+    pub const IEXTEN: tcflag_t = 0o100000;
+
+    // Control character indices.
+    pub const VTIME: usize = 5;
+    pub const VMIN:  usize = 6;
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+mod flags {
+    use super::tcflag_t;
+
+    pub const NCCS: usize = 20;
+
+    // Input flags.
+    pub const IGNBRK: tcflag_t = 0x00000001;
+    pub const BRKINT: tcflag_t = 0x00000002;
+    pub const PARMRK: tcflag_t = 0x00000008;
+    pub const ISTRIP: tcflag_t = 0x00000020;
+    pub const INLCR:  tcflag_t = 0x00000040;
+    pub const IGNCR:  tcflag_t = 0x00000080;
+    pub const ICRNL:  tcflag_t = 0x00000100;
+    pub const IXON:   tcflag_t = 0x00000200;
+
+    // Output flags.
+    pub const OPOST: tcflag_t = 0x00000001;
+
+    // Control flags.
+    pub const CSIZE:  tcflag_t = 0x00000300;
+    pub const CS8:    tcflag_t = 0x00000300;
+    pub const PARENB: tcflag_t = 0x00001000;
+
+    // Local flags.
+    pub const ECHO:   tcflag_t = 0x00000008;
+    pub const ECHONL: tcflag_t = 0x00000010;
+    pub const ISIG:   tcflag_t = 0x00000080;
+    pub const ICANON: tcflag_t = 0x00000100;
+    pub const IEXTEN: tcflag_t = 0x00000400;
+
+    // Control character indices.
+    pub const VMIN:  usize = 16;
+    pub const VTIME: usize = 17;
+}
+
+use flags::*;
+
 const TCSANOW: i32 = 0;
-const ICANON:  i32 = 2;
-const ECHO:    i32 = 10;
 
+#[cfg(any(target_os = "linux", target_os = "android"))]
 #[repr(C)]
 #[derive(Default, Copy, Clone)]
 struct Termios {
-    input_flags:         i32,       // tcflag_t c_iflag;
-    output_flags:        i32,       // tcflag_t c_oflag;
-    control_flags:       i32,       // tcflag_t c_cflag;
-    local_flags:         i32,       // tcflag_t c_lflag;
-    line_discipline:     i32,       // cc_t     c_line;
-    control_characters: [i32; 32],  // cc_t     c_cc[32];
-    input_speed:         i32,       // speed_t  c_ispeed;
-    output_speed:        i32,       // speed_t  c_ospeed;
+    input_flags:         tcflag_t,    // tcflag_t c_iflag;
+    output_flags:        tcflag_t,    // tcflag_t c_oflag;
+    control_flags:       tcflag_t,    // tcflag_t c_cflag;
+    local_flags:         tcflag_t,    // tcflag_t c_lflag;
+    line_discipline:     u8,          // cc_t     c_line;
+    control_characters: [u8; NCCS],   // cc_t     c_cc[NCCS];
+    input_speed:         tcflag_t,    // speed_t  c_ispeed;
+    output_speed:        tcflag_t,    // speed_t  c_ospeed;
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+#[repr(C)]
+#[derive(Default, Copy, Clone)]
+struct Termios {
+    input_flags:         tcflag_t,    // tcflag_t c_iflag;
+    output_flags:        tcflag_t,    // tcflag_t c_oflag;
+    control_flags:       tcflag_t,    // tcflag_t c_cflag;
+    local_flags:         tcflag_t,    // tcflag_t c_lflag;
+    control_characters: [u8; NCCS],   // cc_t     c_cc[NCCS];
+    input_speed:         tcflag_t,    // speed_t  c_ispeed;
+    output_speed:        tcflag_t,    // speed_t  c_ospeed;
+}
+
+impl Termios {
+    fn get(fd: i32) -> io::Result<Termios> {
+        let mut termios = Termios::default();
+        let result = unsafe { tcgetattr(fd, &mut termios as *mut Termios) };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        return Ok(termios);
+    }
+
+    fn set(&self, fd: i32) -> io::Result<()> {
+        let result = unsafe { tcsetattr(fd, TCSANOW, self as *const Termios) };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        return Ok(());
+    }
+
+    /// Turns off line buffering and echo, reads return as soon as a single byte is available.
+    fn set_cbreak(&mut self) {
+        self.local_flags &= !(ICANON | ECHO);
+        self.control_characters[VMIN]  = 1;
+        self.control_characters[VTIME] = 0;
+    }
+
+    /// Same settings as `cfmakeraw`, optionally keeping the signal keys.
+    fn set_raw(&mut self, config: RawConfig) {
+        self.input_flags   &= !(IGNBRK | BRKINT | PARMRK | ISTRIP | INLCR | IGNCR | ICRNL | IXON);
+        self.output_flags  &= !OPOST;
+        self.local_flags   &= !(ECHO | ECHONL | ICANON | IEXTEN);
+        self.control_flags &= !(CSIZE | PARENB);
+        self.control_flags |= CS8;
+
+        if !config.signals {
+            self.local_flags &= !ISIG;
+        }
+
+        self.control_characters[VMIN]  = 1;
+        self.control_characters[VTIME] = 0;
+    }
 }
 
 const POLLIN: i16 = 1;
 
+const SIGWINCH: i32 = 28;
+const F_SETFD:  i32 = 2;
+const F_GETFL:  i32 = 3;
+const F_SETFL:  i32 = 4;
+
+const FD_CLOEXEC: i32 = 1;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const O_NONBLOCK: i32 = 0o4000;
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const O_NONBLOCK: i32 = 0x0004;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const TIOCGWINSZ: std::ffi::c_ulong = 0x5413;
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const TIOCGWINSZ: std::ffi::c_ulong = 0x40087468;
+
+#[repr(C)]
+#[derive(Default, Copy, Clone)]
+struct WinSize {
+    rows:         u16,  // unsigned short ws_row
+    columns:      u16,  // unsigned short ws_col
+    pixel_width:  u16,  // unsigned short ws_xpixel
+    pixel_height: u16,  // unsigned short ws_ypixel
+}
+
 #[repr(C)]
 #[derive(Default, Copy, Clone)]
 struct PollFd {
@@ -35,146 +254,764 @@ struct PollFd {
     return_events:   i16,  // short revents
 }
 
+#[cfg(not(target_vendor = "apple"))]
+#[allow(non_camel_case_types)]
+type nfds_t = std::ffi::c_ulong;
+
+#[cfg(target_vendor = "apple")]
+#[allow(non_camel_case_types)]
+type nfds_t = std::ffi::c_uint;
+
 extern "C" {
     fn tcgetattr(fd: i32, termios: *mut Termios) -> i32;
     fn tcsetattr(fd: i32, optional_actions: i32, termios: *const Termios) -> i32;
-    fn read(fd: i32, buffer: *mut void, buffer_size: usize) -> i32;
-    fn write(fd: i32, buffer: *const void, buffer_size: usize) -> i32;
-    fn poll(fds: *mut PollFd, fds_count: u64, timeout: i32) -> i32;
+    fn isatty(fd: i32) -> i32;
+    fn read(fd: i32, buffer: *mut void, buffer_size: usize) -> isize;
+    fn write(fd: i32, buffer: *const void, buffer_size: usize) -> isize;
+    fn poll(fds: *mut PollFd, fds_count: nfds_t, timeout: i32) -> i32;
+    fn pipe(fds: *mut i32) -> i32;
+    fn fcntl(fd: i32, command: i32, ...) -> i32;
+    fn signal(signal: i32, handler: extern "C" fn(i32)) -> usize;
+    fn ioctl(fd: i32, request: std::ffi::c_ulong, ...) -> i32;
     // fn setlocale(category: i32, locale: *const u8) -> *const u8;
 }
 
-pub fn terma_init() {
+// NOTE: Terminal settings from before raw mode was entered. Set for as long as a RawMode guard
+//       is alive, which is also what tells the readers to leave the terminal settings alone.
+static RAW_MODE: Mutex<Option<Termios>> = Mutex::new(None);
+static PANIC_HOOK: Once = Once::new();
+
+/// Detects the colour depth and whether output gets styled, and installs a SIGWINCH handler
+/// for `Event::Resize`. The handler replaces any SIGWINCH handler set up before.
+pub fn terma_init() -> Result<()> {
+    resize_init();
+    color::depth_set(color::depth_detect(ColorDepth::Ansi16));
+    color::style_detect(unsafe { isatty(STDOUT) } == 1);
+    return Ok(());
+}
 
+/// Keeps the terminal in raw mode for as long as it is alive.
+///
+/// Input is no longer line buffered or echoed, and depending on the `RawConfig` the signal
+/// keys are delivered as regular key events. The previous settings are restored when the guard
+/// is dropped, including when the program panics.
+pub struct RawMode {
+    // Only the guard which actually switched the terminal into raw mode restores it.
+    owner: bool,
+    // Set when the guard switched to the alternate screen as well.
+    alternate_screen: bool,
 }
 
-unsafe fn flush_stdin() {
-    let mut pollfd = PollFd {
-        file_descriptor: STDIN,
-        request_events:  POLLIN,
-        return_events:   0,
-    };
+impl RawMode {
+    pub fn enter() -> Result<RawMode> {
+        return RawMode::enter_with(RawConfig::default());
+    }
+
+    pub fn enter_with(config: RawConfig) -> Result<RawMode> {
+        let mut raw_mode = RAW_MODE.lock().unwrap_or_else(|error| error.into_inner());
+        if raw_mode.is_some() {
+            return Ok(RawMode { owner: false, alternate_screen: false });
+        }
+
+        if unsafe { isatty(STDIN) } != 1 {
+            return Err(Error::NotATty);
+        }
 
-    let mut poll_result = poll(&mut pollfd as *mut PollFd, 1, 0);
-    if poll_result == -1 {
-        return;
+        let old_settings = Termios::get(STDIN)?;
+
+        let mut new_settings = old_settings;
+        new_settings.set_raw(config);
+        new_settings.set(STDIN)?;
+
+        *raw_mode = Some(old_settings);
+
+        PANIC_HOOK.call_once(|| {
+            let previous_hook = panic::take_hook();
+            panic::set_hook(Box::new(move |info| {
+                raw_mode_restore();
+                ansi::alternate_screen_restore();
+                ansi::cursor_shape_restore();
+                previous_hook(info);
+            }));
+        });
+
+        if config.alternate_screen {
+            // NOTE: Let go of the lock first, the restore below needs it.
+            drop(raw_mode);
+            if let Err(error) = alternate_screen_enter() {
+                raw_mode_restore();
+                return Err(error);
+            }
+        }
+
+        return Ok(RawMode { owner: true, alternate_screen: config.alternate_screen });
     }
+}
 
-    while pollfd.return_events != 0 {
-        let mut buffer = [0u8; 1024];
-        let _ = read(STDIN, buffer.as_mut_ptr() as *mut void, buffer.len());
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        if self.owner {
+            if self.alternate_screen {
+                ansi::alternate_screen_restore();
+            }
 
-        poll_result = poll(&mut pollfd as *mut PollFd, 1, 0);
-        if poll_result == -1 {
-            return;
+            ansi::cursor_shape_restore();
+
+            raw_mode_restore();
         }
     }
 }
 
-pub fn print_str(string: &str) -> isize {
-    unsafe {
-        let bytes_written = write(
-            STDOUT,
-            string.as_ptr() as *const void, 
-            string.len()
-        );
+fn raw_mode_restore() {
+    // NOTE: Also called from the panic hook, which must not block on a lock held by the
+    //       panicking thread.
+    let mut raw_mode = match RAW_MODE.try_lock() {
+        Ok(raw_mode) => raw_mode,
+        Err(TryLockError::Poisoned(error)) => error.into_inner(),
+        Err(TryLockError::WouldBlock) => return,
+    };
 
-        return bytes_written as isize;
+    if let Some(old_settings) = raw_mode.take() {
+        let _ = old_settings.set(STDIN);
     }
 }
 
-pub fn print_buf(buffer: &[u8], buffer_size: usize) -> isize {
-    unsafe {
-        let bytes_written = write(
-            STDOUT,
-            buffer.as_ptr() as *const void, 
-            buffer_size
-        );
+fn raw_mode_active() -> bool {
+    return RAW_MODE.lock().unwrap_or_else(|error| error.into_inner()).is_some();
+}
+
+/// Runs `function` with line buffering and echo turned off. Without an active RawMode guard
+/// the settings are only switched for the duration of the call.
+///
+/// NOTE: Pending input is kept, keys typed ahead of a read are meant for it.
+fn with_raw_input<T>(function: impl FnOnce() -> T) -> T {
+    return fd_with_raw_input(STDIN, raw_mode_active(), function);
+}
 
-        return bytes_written as isize;
+/// Same as `with_raw_input` for the terminal behind `fd`, with `raw` telling whether it is in
+/// raw mode already.
+fn fd_with_raw_input<T>(fd: i32, raw: bool, function: impl FnOnce() -> T) -> T {
+    if raw {
+        return function();
     }
+
+    // Not a terminal, there are no settings to change.
+    let Ok(old_settings) = Termios::get(fd) else {
+        return function();
+    };
+
+    let mut new_settings = old_settings;
+    new_settings.set_cbreak();
+    let _ = new_settings.set(fd);
+
+    let result = function();
+
+    let _ = old_settings.set(fd);
+    return result;
 }
 
-pub fn read_buf(buffer: &mut [u8]) -> isize {
-    unsafe {
-        let bytes_read = read(
-            STDIN,
-            buffer.as_mut_ptr() as *mut void,
-            buffer.len()
-        );
+pub fn print_str(string: &str) -> Result<usize> {
+    return print_buf(string.as_bytes(), string.len());
+}
 
-        return bytes_read as isize;
+pub fn print_buf(buffer: &[u8], buffer_size: usize) -> Result<usize> {
+    let buffer = &buffer[..buffer_size.min(buffer.len())];
+    return fd_write(STDOUT, buffer);
+}
+
+fn fd_write(fd: i32, buffer: &[u8]) -> Result<usize> {
+    let mut total_written = 0;
+    while total_written < buffer.len() {
+        let remaining = &buffer[total_written..];
+        let bytes_written = unsafe {
+            write(
+                fd,
+                remaining.as_ptr() as *const void, 
+                remaining.len()
+            )
+        };
+
+        if bytes_written < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+
+            return Err(Error::Io(error));
+        }
+
+        total_written += bytes_written as usize;
     }
+
+    return Ok(total_written);
 }
 
-pub fn read_key() -> KeyCode {
-    unsafe {
-        let mut old_settings = Termios::default();
-        tcgetattr(STDIN, &mut old_settings as *mut Termios);
+pub fn read_buf(buffer: &mut [u8]) -> Result<usize> {
+    return fd_read(STDIN, buffer);
+}
 
-        let mut new_settings = old_settings;
-        new_settings.local_flags &= !(ICANON | ECHO);
+fn fd_read(fd: i32, buffer: &mut [u8]) -> Result<usize> {
+    loop {
+        let bytes_read = unsafe {
+            read(
+                fd,
+                buffer.as_mut_ptr() as *mut void,
+                buffer.len()
+            )
+        };
+
+        if bytes_read < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+
+            return Err(Error::Io(error));
+        }
 
-        tcsetattr(STDIN, TCSANOW, &new_settings as *const Termios);
+        return Ok(bytes_read as usize);
+    }
+}
 
-        flush_stdin();
+pub fn read_event() -> Result<Event> {
+    resize_init();
+    let event = with_raw_input(|| ansi::read_event(None))?;
+
+    // NOTE: Without a timeout the reader only returns once it has an event.
+    return event.ok_or(Error::Timeout);
+}
 
-        let keycode = ansi::read_key();
+/// Same as `read_event`, giving up once `timeout` runs out.
+pub fn poll_event(timeout: Duration) -> Result<Option<Event>> {
+    resize_init();
+    return with_raw_input(|| ansi::read_event(Some(timeout)));
+}
+
+pub fn read_key() -> Result<KeyCode> {
+    return Ok(read_key_event()?.code);
+}
+
+pub fn read_key_event() -> Result<KeyEvent> {
+    let event = with_raw_input(|| ansi::read_key_event(None))?;
+
+    // NOTE: Without a timeout the reader only returns once it has a key.
+    return event.ok_or(Error::Timeout);
+}
+
+pub fn try_read_key() -> Result<Option<KeyCode>> {
+    return read_key_timeout(Duration::ZERO);
+}
+
+pub fn read_key_timeout(timeout: Duration) -> Result<Option<KeyCode>> {
+    let event = with_raw_input(|| ansi::read_key_event(Some(timeout)))?;
+    return Ok(event.map(|event| event.code));
+}
+
+/// Waits until stdin has input available, the terminal gets resized or `timeout` runs out.
+/// Waits indefinitely when `timeout` is `None`.
+pub(crate) fn wait_input(timeout: Option<Duration>) -> Wake {
+    return fd_wait_input(STDIN, timeout);
+}
+
+fn fd_wait_input(fd: i32, timeout: Option<Duration>) -> Wake {
+    let start = Instant::now();
+    loop {
+        let mut pollfds = [
+            PollFd {
+                file_descriptor: fd,
+                request_events:  POLLIN,
+                return_events:   0,
+            },
+            // NOTE: Negative descriptors are ignored by poll, in case the pipe is not set up.
+            PollFd {
+                file_descriptor: RESIZE_PIPE[0].load(Ordering::Relaxed),
+                request_events:  POLLIN,
+                return_events:   0,
+            },
+        ];
+
+        let poll_timeout = match timeout {
+            Some(timeout) => {
+                let remaining = timeout.saturating_sub(start.elapsed());
+                remaining.as_millis().min(i32::MAX as u128) as i32
+            }
+            None => -1,
+        };
+
+        let poll_result = unsafe {
+            poll(pollfds.as_mut_ptr(), pollfds.len() as nfds_t, poll_timeout)
+        };
+
+        // Interrupted by a signal, most likely SIGWINCH which shows up on the pipe next time.
+        if poll_result < 0 {
+            continue;
+        }
+
+        if poll_result == 0 {
+            return Wake::Timeout;
+        }
 
-        tcsetattr(STDIN, TCSANOW, &old_settings as *const Termios);
-        return keycode;
+        if pollfds[1].return_events != 0 {
+            let mut buffer = [0u8; 64];
+            unsafe {
+                while read(pollfds[1].file_descriptor, buffer.as_mut_ptr() as *mut void, buffer.len()) > 0 {}
+            }
+
+            RESIZE_PENDING.store(true, Ordering::Relaxed);
+            return Wake::Resize;
+        }
+
+        return Wake::Input;
     }
 }
 
-pub fn cursor_set(x: u16, y: u16) {
-    ansi::cursor_set(x, y);
+/// Returns true once after every resize seen by `wait_input`.
+pub(crate) fn resize_take() -> bool {
+    return RESIZE_PENDING.swap(false, Ordering::Relaxed);
+}
+
+/// Returns true when the output understands escape sequences, which it always does here.
+pub(crate) fn ansi_supported() -> bool {
+    return true;
 }
 
-pub fn cursor_get() -> Pos {
+// NOTE: Self-pipe written to by the SIGWINCH handler so that resizes wake up poll. Read end
+//       first, both are -1 until resize_init runs.
+static RESIZE_PIPE: [AtomicI32; 2] = [AtomicI32::new(-1), AtomicI32::new(-1)];
+static RESIZE_PENDING: AtomicBool = AtomicBool::new(false);
+static RESIZE_INIT: Once = Once::new();
+
+extern "C" fn resize_signal(_signal: i32) {
+    // NOTE: Only async-signal-safe calls in here. The pipe is non-blocking, if it is full
+    //       the reader has a wake-up pending already.
+    let byte = 1u8;
     unsafe {
-        let mut old_settings = Termios::default();
-        tcgetattr(STDIN, &mut old_settings as *mut Termios);
+        let _ = write(RESIZE_PIPE[1].load(Ordering::Relaxed), &byte as *const u8 as *const void, 1);
+    }
+}
 
-        let mut new_settings = old_settings;
-        new_settings.local_flags &= !(ICANON | ECHO);
+/// Installs the SIGWINCH handler behind `Event::Resize`, the first time it is called.
+// NOTE: Replaces whatever handler the application had installed for SIGWINCH.
+fn resize_init() {
+    RESIZE_INIT.call_once(|| unsafe {
+        let mut fds = [-1i32; 2];
+        if pipe(fds.as_mut_ptr()) != 0 {
+            return;
+        }
+
+        // NOTE: Kept out of child processes, an editor started from the application must
+        //       not hold on to the pipe.
+        for fd in fds {
+            let flags = fcntl(fd, F_GETFL);
+            fcntl(fd, F_SETFL, flags | O_NONBLOCK);
+            fcntl(fd, F_SETFD, FD_CLOEXEC);
+        }
 
-        tcsetattr(STDIN, TCSANOW, &new_settings as *const Termios);
+        RESIZE_PIPE[0].store(fds[0], Ordering::Relaxed);
+        RESIZE_PIPE[1].store(fds[1], Ordering::Relaxed);
 
-        flush_stdin();
+        signal(SIGWINCH, resize_signal);
+    });
+}
 
-        let pos = ansi::cursor_get();
+pub fn cursor_set(x: u16, y: u16) -> Result<()> {
+    return ansi::print_with(|out| ansi::cursor_set(out, x, y));
+}
 
-        tcsetattr(STDIN, TCSANOW, &old_settings as *const Termios);
-        return pos;
-    }
+/// Moves the cursor up by `count` lines, stopping at the top of the screen.
+pub fn cursor_up(count: u16) -> Result<()> {
+    return ansi::print_with(|out| ansi::cursor_up(out, count));
 }
 
-pub fn console_clear() {
-    ansi::console_clear();
+pub fn cursor_down(count: u16) -> Result<()> {
+    return ansi::print_with(|out| ansi::cursor_down(out, count));
 }
 
-pub fn color_reset() {
-    ansi::color_reset();
+pub fn cursor_left(count: u16) -> Result<()> {
+    return ansi::print_with(|out| ansi::cursor_left(out, count));
 }
 
-pub fn color_bg(red: u8, green: u8, blue: u8) {
-    ansi::color_bg(red, green, blue);
+pub fn cursor_right(count: u16) -> Result<()> {
+    return ansi::print_with(|out| ansi::cursor_right(out, count));
 }
 
-pub fn color_fg(red: u8, green: u8, blue: u8) {
-    ansi::color_fg(red, green, blue);
+/// Moves the cursor to the start of the line `count` lines down.
+pub fn cursor_line_next(count: u16) -> Result<()> {
+    return ansi::print_with(|out| ansi::cursor_line_next(out, count));
 }
 
-pub fn buffer_size() -> Pos {
-    let prev = cursor_get();
+/// Moves the cursor to the start of the line `count` lines up.
+pub fn cursor_line_prev(count: u16) -> Result<()> {
+    return ansi::print_with(|out| ansi::cursor_line_prev(out, count));
+}
+
+/// Moves the cursor to column `x` of the current line.
+pub fn cursor_column(x: u16) -> Result<()> {
+    return ansi::print_with(|out| ansi::cursor_column(out, x));
+}
+
+/// Remembers the cursor position, to go back to it with `cursor_restore`.
+pub fn cursor_save() -> Result<()> {
+    return ansi::print_with(ansi::cursor_save);
+}
+
+pub fn cursor_restore() -> Result<()> {
+    return ansi::print_with(ansi::cursor_restore);
+}
+
+pub fn cursor_hide() -> Result<()> {
+    return ansi::print_with(ansi::cursor_hide);
+}
+
+pub fn cursor_show() -> Result<()> {
+    return ansi::print_with(ansi::cursor_show);
+}
+
+/// Changes the look of the cursor. A changed shape is reset when the `RawMode` guard is
+/// dropped or the program panics.
+pub fn cursor_shape(shape: CursorShape) -> Result<()> {
+    return ansi::print_with(|out| ansi::cursor_shape(out, shape));
+}
+
+pub fn cursor_get() -> Result<Pos> {
+    // NOTE: Not flushing stdin, input which arrives before the report stays queued for
+    //       the event readers.
+    return with_raw_input(ansi::cursor_get);
+}
 
-    cursor_set(u16::MAX, u16::MAX);
+/// Makes the terminal report mouse input, delivered as `Event::Mouse` by `read_event`.
+pub fn mouse_enable(mode: MouseMode) -> Result<()> {
+    return ansi::print_with(|out| ansi::mouse_enable(out, mode));
+}
 
-    let mut size = cursor_get();
+pub fn mouse_disable() -> Result<()> {
+    return ansi::print_with(ansi::mouse_disable);
+}
+
+/// Switches to the alternate screen, which full screen applications draw on so that the
+/// shell output is shown again once they are done. See also `RawConfig::alternate_screen`.
+pub fn alternate_screen_enter() -> Result<()> {
+    return ansi::print_with(ansi::alternate_screen_enter);
+}
+
+pub fn alternate_screen_leave() -> Result<()> {
+    return ansi::print_with(ansi::alternate_screen_leave);
+}
+
+/// Makes the terminal mark pasted text, delivered as a single `Event::Paste` by `read_event`.
+pub fn paste_enable() -> Result<()> {
+    return ansi::print_with(ansi::paste_enable);
+}
+
+pub fn paste_disable() -> Result<()> {
+    return ansi::print_with(ansi::paste_disable);
+}
+
+/// Makes the terminal report focus changes, delivered as `Event::FocusGained` and
+/// `Event::FocusLost` by `read_event`.
+pub fn focus_enable() -> Result<()> {
+    return ansi::print_with(ansi::focus_enable);
+}
+
+pub fn focus_disable() -> Result<()> {
+    return ansi::print_with(ansi::focus_disable);
+}
+
+pub fn console_clear() -> Result<()> {
+    return ansi::print_with(ansi::console_clear);
+}
+
+/// Erases part of the screen, see `ClearType`. The cursor stays where it is.
+pub fn console_erase(clear_type: ClearType) -> Result<()> {
+    return ansi::print_with(|out| ansi::console_erase(out, clear_type));
+}
+
+pub fn color_reset() -> Result<()> {
+    return ansi::print_with(|out| ansi::color_reset(out, color::style_enabled()));
+}
+
+pub fn color_bg(red: u8, green: u8, blue: u8) -> Result<()> {
+    return ansi::print_with(|out| ansi::color_bg(out, color::style_enabled(), red, green, blue));
+}
+
+pub fn color_fg(red: u8, green: u8, blue: u8) -> Result<()> {
+    return ansi::print_with(|out| ansi::color_fg(out, color::style_enabled(), red, green, blue));
+}
+
+/// Sets the colour of underlines, independent of the text colour.
+pub fn color_underline(red: u8, green: u8, blue: u8) -> Result<()> {
+    return ansi::print_with(|out| ansi::color_underline(out, color::style_enabled(), red, green, blue));
+}
+
+/// Returns how many colours the terminal can show, as detected by `terma_init`. Colours are
+/// downsampled to the closest ones available.
+pub fn color_depth() -> ColorDepth {
+    return color::depth();
+}
+
+/// Overrides the detected colour depth.
+pub fn color_depth_set(depth: ColorDepth) {
+    color::depth_set(depth);
+}
+
+/// Asks the terminal itself whether it shows 24-bit colours, for terminals which do not
+/// advertise it through the environment. Resets the background colour.
+pub fn color_depth_query() -> Result<ColorDepth> {
+    return with_raw_input(ansi::color_depth_query);
+}
+
+/// Returns the policy deciding whether colours and text attributes are sent.
+pub fn style_policy() -> StylePolicy {
+    return color::style_policy();
+}
+
+pub fn style_policy_set(policy: StylePolicy) {
+    color::style_policy_set(policy);
+}
+
+/// Returns true when colours and text attributes are sent under the current policy. Colour
+/// changes are skipped as well when `color_depth` is `ColorDepth::None`.
+pub fn style_enabled() -> bool {
+    return color::style_enabled();
+}
+
+pub fn color_fg_set(color: Color) -> Result<()> {
+    return ansi::print_with(|out| ansi::color_fg_set(out, color::style_enabled(), color));
+}
+
+pub fn color_bg_set(color: Color) -> Result<()> {
+    return ansi::print_with(|out| ansi::color_bg_set(out, color::style_enabled(), color));
+}
+
+pub fn color_underline_set(color: Color) -> Result<()> {
+    return ansi::print_with(|out| ansi::color_underline_set(out, color::style_enabled(), color));
+}
+
+/// Turns on the given text attributes, keeping the ones already active.
+pub fn attributes_set(attributes: Attributes) -> Result<()> {
+    return ansi::print_with(|out| ansi::attributes_set(out, color::style_enabled(), attributes));
+}
+
+/// Turns off the given text attributes without resetting colours.
+pub fn attributes_clear(attributes: Attributes) -> Result<()> {
+    return ansi::print_with(|out| ansi::attributes_clear(out, color::style_enabled(), attributes));
+}
+
+pub fn buffer_size() -> Result<Pos> {
+    return Ok(window_size()?.size);
+}
+
+/// Returns the size of the terminal in cells and in pixels.
+pub fn window_size() -> Result<WindowSize> {
+    if let Ok(window_size) = window_size_tracked() {
+        return Ok(window_size);
+    }
+
+    // NOTE: A terminal which does not track its size (some serial consoles), ask the terminal
+    //       itself by moving the cursor as far as it goes. Fails with a timeout when nothing
+    //       answers. Without a terminal on both ends the query would end up in a file or pipe.
+    if unsafe { isatty(STDOUT) != 1 || isatty(STDIN) != 1 } {
+        return Err(Error::NotATty);
+    }
+
+    let prev = cursor_get()?;
+
+    cursor_set(u16::MAX, u16::MAX)?;
+
+    let mut size = cursor_get()?;
     size.x = size.x.saturating_add(1);
     size.y = size.y.saturating_add(1);
 
-    cursor_set(prev.x, prev.y);
+    cursor_set(prev.x, prev.y)?;
+
+    return Ok(WindowSize { size, pixels: Pos::default() });
+}
+
+/// Size of the terminal as tracked by the kernel, without asking the terminal itself.
+pub(crate) fn window_size_tracked() -> Result<WindowSize> {
+    for fd in [STDOUT, STDIN] {
+        if let Some(window_size) = fd_window_size(fd) {
+            return Ok(window_size);
+        }
+    }
+
+    return Err(Error::NotATty);
+}
+
+/// Size of the terminal behind `fd` as tracked by the kernel, if it is a terminal at all.
+fn fd_window_size(fd: i32) -> Option<WindowSize> {
+    let mut win_size = WinSize::default();
+    let result = unsafe { ioctl(fd, TIOCGWINSZ, &mut win_size as *mut WinSize) };
+    if result != 0 || win_size.columns == 0 || win_size.rows == 0 {
+        return None;
+    }
+
+    let window_size = WindowSize {
+        size:   Pos { x: win_size.columns,     y: win_size.rows },
+        pixels: Pos { x: win_size.pixel_width, y: win_size.pixel_height },
+    };
+    return Some(window_size);
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Fds {
+    input:  i32,
+    output: i32,
+}
+
+impl Device for Fds {
+    fn input_wait(&self, timeout: Option<Duration>) -> Wake {
+        return fd_wait_input(self.input, timeout);
+    }
+
+    fn input_read(&self, buffer: &mut [u8]) -> Result<usize> {
+        return fd_read(self.input, buffer);
+    }
+
+    fn output_write(&self, buffer: &[u8]) -> Result<()> {
+        fd_write(self.output, buffer)?;
+        return Ok(());
+    }
+
+    fn size(&self) -> Result<Pos> {
+        let window_size = fd_window_size(self.output).or_else(|| fd_window_size(self.input));
+        return window_size.map(|window_size| window_size.size).ok_or(Error::NotATty);
+    }
+}
+
+/// Input and output of a `Terminal`, which are the standard streams, the controlling terminal
+/// or any pair of file descriptors.
+pub(crate) struct Handle {
+    fds: Fds,
+    // NOTE: Keeps an opened /dev/tty alive, it is closed together with the handle.
+    _file: Option<File>,
+    // NOTE: Input read through the handle is queued here and not in the one of the process.
+    parser: Parser,
+    // Settings from before `raw_mode_enter`, restored when leaving raw mode.
+    raw_settings: Option<Termios>,
+}
+
+impl Handle {
+    pub(crate) fn stdio() -> Handle {
+        return Handle::from_fds(STDIN, STDOUT);
+    }
+
+    /// Opens the controlling terminal, which also works when the standard streams are
+    /// redirected.
+    pub(crate) fn tty_open() -> Result<Handle> {
+        let file = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+        let fd = file.as_raw_fd();
+
+        let mut handle = Handle::from_fds(fd, fd);
+        handle._file = Some(file);
+        return Ok(handle);
+    }
+
+    pub(crate) fn from_fds(input: i32, output: i32) -> Handle {
+        return Handle {
+            fds: Fds { input, output },
+            _file: None,
+            parser: Parser::new(),
+            raw_settings: None,
+        };
+    }
+
+    /// What `StylePolicy::Auto` comes down to, `None` when it follows the standard output.
+    pub(crate) fn style_auto(&self) -> Option<bool> {
+        if self.fds.output == STDOUT {
+            return None;
+        }
+
+        let is_tty = unsafe { isatty(self.fds.output) } == 1;
+        return Some(color::style_auto(is_tty));
+    }
+
+    pub(crate) fn write(&self, buffer: &[u8]) -> Result<()> {
+        return self.fds.output_write(buffer);
+    }
+
+    pub(crate) fn read_buf(&self, buffer: &mut [u8]) -> Result<usize> {
+        return fd_read(self.fds.input, buffer);
+    }
+
+    fn with_raw_input<T>(&mut self, function: impl FnOnce(&Fds, &mut Parser) -> T) -> T {
+        // NOTE: The RawMode guard only covers the standard input.
+        let raw = self.raw_settings.is_some() || (self.fds.input == STDIN && raw_mode_active());
+        return fd_with_raw_input(self.fds.input, raw, || function(&self.fds, &mut self.parser));
+    }
 
-    return size;
+    /// Reads the next event, same as `read_event` and `poll_event` do.
+    pub(crate) fn read_event(&mut self, timeout: Option<Duration>) -> Result<Option<Event>> {
+        resize_init();
+        return self.with_raw_input(|fds, parser| ansi::device_read_event(fds, parser, timeout));
+    }
+
+    pub(crate) fn read_key_event(&mut self, timeout: Option<Duration>) -> Result<Option<KeyEvent>> {
+        return self.with_raw_input(|fds, parser| ansi::device_read_key_event(fds, parser, timeout));
+    }
+
+    pub(crate) fn cursor_get(&mut self) -> Result<Pos> {
+        return self.with_raw_input(ansi::device_cursor_get);
+    }
+
+    pub(crate) fn color_depth_query(&mut self) -> Result<ColorDepth> {
+        return self.with_raw_input(ansi::device_color_depth_query);
+    }
+
+    pub(crate) fn window_size(&mut self) -> Result<WindowSize> {
+        for fd in [self.fds.output, self.fds.input] {
+            if let Some(window_size) = fd_window_size(fd) {
+                return Ok(window_size);
+            }
+        }
+
+        // NOTE: Same fallback as `window_size`, through this terminal.
+        if unsafe { isatty(self.fds.output) != 1 || isatty(self.fds.input) != 1 } {
+            return Err(Error::NotATty);
+        }
+
+        let prev = self.cursor_get()?;
+        ansi::device_print_with(&self.fds, |out| ansi::cursor_set(out, u16::MAX, u16::MAX))?;
+
+        let mut size = self.cursor_get()?;
+        size.x = size.x.saturating_add(1);
+        size.y = size.y.saturating_add(1);
+
+        ansi::device_print_with(&self.fds, |out| ansi::cursor_set(out, prev.x, prev.y))?;
+
+        return Ok(WindowSize { size, pixels: Pos::default() });
+    }
+
+    pub(crate) fn raw_mode_enter(&mut self, config: RawConfig) -> Result<()> {
+        if self.raw_settings.is_some() {
+            return Ok(());
+        }
+
+        if unsafe { isatty(self.fds.input) } != 1 {
+            return Err(Error::NotATty);
+        }
+
+        let old_settings = Termios::get(self.fds.input)?;
+
+        let mut new_settings = old_settings;
+        new_settings.set_raw(config);
+        new_settings.set(self.fds.input)?;
+
+        self.raw_settings = Some(old_settings);
+        return Ok(());
+    }
+
+    pub(crate) fn raw_mode_leave(&mut self) -> Result<()> {
+        if let Some(old_settings) = self.raw_settings.take() {
+            old_settings.set(self.fds.input)?;
+        }
+
+        return Ok(());
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        let _ = self.raw_mode_leave();
+    }
 }
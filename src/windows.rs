@@ -1,6 +1,25 @@
+use super::Attributes;
+use super::ClearType;
+use super::Color;
+use super::ColorDepth;
+use super::CursorShape;
 use super::KeyCode;
+use super::KeyEvent;
+use super::{Event, MouseButton, MouseKind, MouseMode};
+use super::Modifiers;
 use super::Pos;
+use super::RawConfig;
+use super::StylePolicy;
+use super::WindowSize;
+use super::{Error, Result};
 use super::ansi;
+use super::color;
+use super::ansi::Wake;
+use std::io;
+use std::panic;
+use std::sync::{Mutex, Once, TryLockError};
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, Ordering};
+use std::time::{Duration, Instant};
 
 #[allow(non_camel_case_types)]
 type void = std::ffi::c_void;
@@ -16,11 +35,16 @@ type void = std::ffi::c_void;
 // Console handle codes.
 const STD_INPUT_HANDLE:  u32 = -10i32 as u32;
 const STD_OUTPUT_HANDLE: u32 = -11i32 as u32;
+const INVALID_HANDLE_VALUE: *const void = -1isize as *const void;
 
 // Input flags.
 const ENABLE_PROCESSED_INPUT: u32        = 0x0001;
 const ENABLE_LINE_INPUT: u32             = 0x0002;
 const ENABLE_ECHO_INPUT: u32             = 0x0004;
+const ENABLE_WINDOW_INPUT: u32           = 0x0008;
+const ENABLE_MOUSE_INPUT: u32            = 0x0010;
+const ENABLE_QUICK_EDIT_MODE: u32        = 0x0040;
+const ENABLE_EXTENDED_FLAGS: u32         = 0x0080;
 const ENABLE_VIRTUAL_TERMINAL_INPUT: u32 = 0x0200;
 
 // Output flags.
@@ -28,12 +52,57 @@ const ENABLE_PROCESSED_OUTPUT: u32            = 0x0001;
 const ENABLE_WRAP_AT_EOL_OUTPUT: u32          = 0x0002;
 const ENABLE_VIRTUAL_TERMINAL_PROCESSING: u32 = 0x0004;
 
+// Input record event types.
+const KEY_EVENT: u16   = 0x0001;
+const MOUSE_EVENT: u16 = 0x0002;
+const WINDOW_BUFFER_SIZE_EVENT: u16 = 0x0004;
+const FOCUS_EVENT: u16 = 0x0010;
+
+// Mouse button state flags.
+const FROM_LEFT_1ST_BUTTON_PRESSED: u32 = 0x0001;
+const RIGHTMOST_BUTTON_PRESSED: u32     = 0x0002;
+const FROM_LEFT_2ND_BUTTON_PRESSED: u32 = 0x0004;
+const FROM_LEFT_3RD_BUTTON_PRESSED: u32 = 0x0008;
+const FROM_LEFT_4TH_BUTTON_PRESSED: u32 = 0x0010;
+
+// Mouse event flags.
+const MOUSE_MOVED: u32    = 0x0001;
+const MOUSE_WHEELED: u32  = 0x0004;
+const MOUSE_HWHEELED: u32 = 0x0008;
+
+// Wait function codes.
+const INFINITE: u32      = 0xffffffff;
+const WAIT_OBJECT_0: u32 = 0x00000000;
+
+// Console text attributes.
+const FOREGROUND_INTENSITY: u16     = 0x0008;
+const COMMON_LVB_REVERSE_VIDEO: u16 = 0x4000;
+const COMMON_LVB_UNDERSCORE: u16    = 0x8000;
+
+// Control key state flags.
+const RIGHT_ALT_PRESSED: u32  = 0x0001;
+const LEFT_ALT_PRESSED: u32   = 0x0002;
+const RIGHT_CTRL_PRESSED: u32 = 0x0004;
+const LEFT_CTRL_PRESSED: u32  = 0x0008;
+const SHIFT_PRESSED: u32      = 0x0010;
+
 // NOTE: Only modified during the initialization (terma_init).
 //       The initialization should happen before any thread is spawned.
 static mut supports_ansi: bool = false;
 static mut stdin:  *const void = std::ptr::null();
 static mut stdout: *const void = std::ptr::null();
 
+// First half of a surrogate pair, waiting for the second key event.
+static HIGH_SURROGATE: AtomicU16 = AtomicU16::new(0);
+
+// Mouse buttons held down as of the last mouse event.
+static MOUSE_BUTTONS: AtomicU32 = AtomicU32::new(0);
+
+static MOUSE_MODE: Mutex<Option<MouseMode>> = Mutex::new(None);
+
+// NOTE: Position stored by `cursor_save` for the legacy console, which has no equivalent.
+static CURSOR_SAVED: Mutex<Option<Pos>> = Mutex::new(None);
+
 #[repr(C)]
 #[derive(Copy, Clone, Default, Debug)]
 struct Coord {
@@ -64,7 +133,7 @@ struct EventMouse {
 
 #[repr(C)]
 #[derive(Copy, Clone)]
-union Event {
+union InputEvent {
     key:   EventKey,    // KEY_EVENT_RECORD          KeyEvent;
     mouse: EventMouse,  // MOUSE_EVENT_RECORD        MouseEvent;
     size:  Coord,       // WINDOW_BUFFER_SIZE_RECORD WindowBufferSizeEvent;
@@ -75,15 +144,15 @@ union Event {
 #[repr(C)]
 #[derive(Copy, Clone)]
 struct InputRecord {
-    event_type: u16,  // WORD  EventType;
-    event: Event,     // union { ... } Event;
+    event_type: u16,    // WORD  EventType;
+    event: InputEvent,  // union { ... } Event;
 }
 
 impl Default for InputRecord {
     fn default() -> Self {
         Self {
             event_type: 0,
-            event: Event { focus: 0 }
+            event: InputEvent { focus: 0 }
         }
     }
 }
@@ -105,6 +174,13 @@ struct SmallRect {
     bottom: i16,  // SHORT Bottom;
 }
 
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct CursorInfo {
+    size:    u32,  // DWORD dwSize;
+    visible: i32,  // BOOL  bVisible;
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Default)]
 struct ConsoleBufferInfo {
@@ -117,7 +193,8 @@ struct ConsoleBufferInfo {
 
 extern "system" {
     fn GetStdHandle(std_handle_code: u32) -> *const void;
-    fn FlushConsoleInputBuffer(handle: *const void) -> i32;
+    fn WaitForSingleObject(handle: *const void, milliseconds: u32) -> u32;
+    fn PeekConsoleInputW(handle: *const void, buffer: *mut InputRecord, buffer_length: i32, entries_read: *mut u32) -> i32;
     fn ReadConsoleInputW(handle: *const void, buffer: *mut InputRecord, buffer_length: i32, entries_read: *mut u32) -> i32;
     fn GetConsoleMode(handle: *const void, mode: *mut u32) -> i32;
     fn SetConsoleMode(handle: *const void, mode: u32) -> i32;
@@ -126,11 +203,15 @@ extern "system" {
     fn WriteConsoleA(handle: *const void, buffer: *const void, buffer_size: u32, bytes_written: *mut u32, reserved: *const void) -> i32;
     fn GetConsoleScreenBufferInfo(handle: *const void, buffer_info: *mut ConsoleBufferInfo) -> i32;
     fn ScrollConsoleScreenBufferW(handle: *const void, scroll: *const SmallRect, clip: *const SmallRect, destination: Coord, fill: *const CharInfo) -> i32;
+    fn FillConsoleOutputCharacterW(handle: *const void, character: u16, length: u32, start: Coord, written: *mut u32) -> i32;
+    fn FillConsoleOutputAttribute(handle: *const void, attributes: u16, length: u32, start: Coord, written: *mut u32) -> i32;
+    fn GetConsoleCursorInfo(handle: *const void, cursor_info: *mut CursorInfo) -> i32;
+    fn SetConsoleCursorInfo(handle: *const void, cursor_info: *const CursorInfo) -> i32;
     fn SetConsoleCursorPosition(handle: *const void, cursor_position: Coord) -> i32;
     fn SetConsoleTextAttribute(handle: *const void, attributes: u16) -> i32;
 }
 
-pub fn terma_init() {
+pub fn terma_init() -> Result<()> {
     unsafe {
         // TODO: perform checks here:
         // print ansi query code
@@ -142,61 +223,359 @@ pub fn terma_init() {
         // ansi -> enable virtual processing
         supports_ansi = false;
 
-        // Return error on null?
         stdin = GetStdHandle(STD_INPUT_HANDLE);
-        if stdin != std::ptr::null() && supports_ansi {
+        if stdin.is_null() || stdin == INVALID_HANDLE_VALUE {
+            return Err(Error::NotATty);
+        }
+
+        if supports_ansi {
             let mut input_mode = 0;
             input_mode |= ENABLE_PROCESSED_INPUT;
             input_mode |= ENABLE_VIRTUAL_TERMINAL_INPUT;
+            input_mode |= ENABLE_WINDOW_INPUT;
             // input_mode |= ENABLE_ECHO_INPUT;
             // input_mode |= ENABLE_LINE_INPUT;
             SetConsoleMode(stdin, input_mode);
+        } else {
+            let mut input_mode = 0;
+            if GetConsoleMode(stdin, &mut input_mode) != 0 {
+                SetConsoleMode(stdin, input_mode | ENABLE_WINDOW_INPUT);
+            }
         }
 
+        // NOTE: The legacy console has 16 colours, but those are mapped separately.
+        color::depth_set(color::depth_detect(ColorDepth::TrueColor));
+
         stdout = GetStdHandle(STD_OUTPUT_HANDLE);
-        if stdout != std::ptr::null() && supports_ansi {
+        if stdout.is_null() || stdout == INVALID_HANDLE_VALUE {
+            return Err(Error::NotATty);
+        }
+
+        // NOTE: Output is styled by default only when it goes to the console.
+        let mut console_mode = 0u32;
+        color::style_detect(GetConsoleMode(stdout, &mut console_mode as *mut u32) != 0);
+
+        if supports_ansi {
             let mut output_mode = 0;
             output_mode |= ENABLE_VIRTUAL_TERMINAL_PROCESSING;
             output_mode |= ENABLE_WRAP_AT_EOL_OUTPUT;
             output_mode |= ENABLE_PROCESSED_OUTPUT;
             SetConsoleMode(stdout, output_mode);
         }
+
+        return Ok(());
+    }
+}
+
+fn last_error() -> Error {
+    return Error::Io(io::Error::last_os_error());
+}
+
+// NOTE: Console input mode from before raw mode was entered. Set for as long as a RawMode guard
+//       is alive.
+static RAW_MODE: Mutex<Option<u32>> = Mutex::new(None);
+static PANIC_HOOK: Once = Once::new();
+
+/// Keeps the console in raw mode for as long as it is alive.
+///
+/// Input is no longer line buffered or echoed, and depending on the `RawConfig` Ctrl+C is
+/// delivered as a regular key event. The previous mode is restored when the guard is dropped,
+/// including when the program panics.
+pub struct RawMode {
+    // Only the guard which actually switched the console into raw mode restores it.
+    owner: bool,
+    // Set when the guard switched to the alternate screen as well.
+    alternate_screen: bool,
+}
+
+impl RawMode {
+    pub fn enter() -> Result<RawMode> {
+        return RawMode::enter_with(RawConfig::default());
+    }
+
+    pub fn enter_with(config: RawConfig) -> Result<RawMode> {
+        let mut raw_mode = RAW_MODE.lock().unwrap_or_else(|error| error.into_inner());
+        if raw_mode.is_some() {
+            return Ok(RawMode { owner: false, alternate_screen: false });
+        }
+
+        unsafe {
+            let mut old_mode = 0u32;
+            if GetConsoleMode(stdin, &mut old_mode as *mut u32) == 0 {
+                return Err(Error::NotATty);
+            }
+
+            let mut new_mode = old_mode & !(ENABLE_LINE_INPUT | ENABLE_ECHO_INPUT);
+            if !config.signals {
+                new_mode &= !ENABLE_PROCESSED_INPUT;
+            }
+
+            if SetConsoleMode(stdin, new_mode) == 0 {
+                return Err(last_error());
+            }
+
+            *raw_mode = Some(old_mode);
+        }
+
+        PANIC_HOOK.call_once(|| {
+            let previous_hook = panic::take_hook();
+            panic::set_hook(Box::new(move |info| {
+                raw_mode_restore();
+                ansi::alternate_screen_restore();
+                ansi::cursor_shape_restore();
+                previous_hook(info);
+            }));
+        });
+
+        if config.alternate_screen {
+            // NOTE: Let go of the lock first, the restore below needs it.
+            drop(raw_mode);
+            if let Err(error) = alternate_screen_enter() {
+                raw_mode_restore();
+                return Err(error);
+            }
+        }
+
+        return Ok(RawMode { owner: true, alternate_screen: config.alternate_screen });
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        if self.owner {
+            if self.alternate_screen {
+                ansi::alternate_screen_restore();
+            }
+
+            ansi::cursor_shape_restore();
+
+            raw_mode_restore();
+        }
+    }
+}
+
+fn raw_mode_restore() {
+    // NOTE: Also called from the panic hook, which must not block on a lock held by the
+    //       panicking thread.
+    let mut raw_mode = match RAW_MODE.try_lock() {
+        Ok(raw_mode) => raw_mode,
+        Err(TryLockError::Poisoned(error)) => error.into_inner(),
+        Err(TryLockError::WouldBlock) => return,
+    };
+
+    if let Some(old_mode) = raw_mode.take() {
+        unsafe {
+            SetConsoleMode(stdin, old_mode);
+        }
     }
 }
 
-unsafe fn fallback_read_key() -> KeyCode {
+unsafe fn fallback_read_key() -> Result<KeyEvent> {
     use std::io::Read;
 
     let mut buffer = [0u8; 3];
-    let _ = std::io::stdin().read(&mut buffer);
-    return KeyCode::Char(char::from_u32_unchecked(buffer[0] as u32));
+    let bytes_read = std::io::stdin().read(&mut buffer)?;
+    if bytes_read == 0 {
+        return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+    }
+
+    return Ok(KeyEvent::new(KeyCode::Char(char::from_u32_unchecked(buffer[0] as u32)), Modifiers::NONE));
+}
+
+// NOTE: Works very poorly on mingw and git bash terminals.
+pub fn read_event() -> Result<Event> {
+    unsafe { 
+        let event = if supports_ansi {
+            ansi::read_event(None)?
+        } else {
+            read_event_legacy(None)?
+        };
+
+        // NOTE: Without a timeout the readers only return once they have an event.
+        return event.ok_or(Error::Timeout);
+    }
+}
+
+/// Same as `read_event`, giving up once `timeout` runs out.
+pub fn poll_event(timeout: Duration) -> Result<Option<Event>> {
+    unsafe {
+        if supports_ansi {
+            return ansi::read_event(Some(timeout));
+        } else {
+            return read_event_legacy(Some(timeout));
+        }
+    }
+}
+
+pub fn read_key() -> Result<KeyCode> {
+    return Ok(read_key_event()?.code);
 }
 
 // NOTE: Works very poorly on mingw and git bash terminals.
-pub fn read_key() -> KeyCode {
+pub fn read_key_event() -> Result<KeyEvent> {
     unsafe { 
-        let _ = FlushConsoleInputBuffer(stdin); 
+        let event = if supports_ansi {
+            ansi::read_key_event(None)?
+        } else {
+            read_key_legacy(None)?
+        };
+
+        // NOTE: Without a timeout the readers only return once they have a key.
+        return event.ok_or(Error::Timeout);
+    }
+}
+
+pub fn try_read_key() -> Result<Option<KeyCode>> {
+    return read_key_timeout(Duration::ZERO);
+}
+
+pub fn read_key_timeout(timeout: Duration) -> Result<Option<KeyCode>> {
+    let event = read_key_event_timeout(timeout)?;
+    return Ok(event.map(|event| event.code));
+}
 
+fn read_key_event_timeout(timeout: Duration) -> Result<Option<KeyEvent>> {
+    unsafe {
         if supports_ansi {
-            return ansi::read_key();
+            return ansi::read_key_event(Some(timeout));
         } else {
-            return read_key_legacy();
+            return read_key_legacy(Some(timeout));
+        }
+    }
+}
+
+/// Waits until the console input has pending events, the console gets resized or `timeout`
+/// runs out. Waits indefinitely when `timeout` is `None`.
+// NOTE: The console input handle is signaled for any input record, including mouse and focus
+//       events. In ANSI mode those are dropped here so that the following read does not block,
+//       in legacy mode the reader handles them itself.
+pub(crate) fn wait_input(timeout: Option<Duration>) -> Wake {
+    let start = Instant::now();
+    unsafe {
+        loop {
+            let wait_timeout = match timeout {
+                Some(timeout) => {
+                    let remaining = timeout.saturating_sub(start.elapsed());
+                    remaining.as_millis().min(INFINITE as u128 - 1) as u32
+                }
+                None => INFINITE,
+            };
+
+            if WaitForSingleObject(stdin, wait_timeout) != WAIT_OBJECT_0 {
+                return Wake::Timeout;
+            }
+
+            if !supports_ansi {
+                return Wake::Input;
+            }
+
+            let mut input = InputRecord::default();
+            let mut entries_read = 0u32;
+            let result = PeekConsoleInputW(stdin, &mut input as *mut InputRecord, 1, &mut entries_read as *mut u32);
+            if result == 0 || entries_read == 0 {
+                return Wake::Input;
+            }
+
+            if input.event_type == KEY_EVENT && input.event.key.key_down != 0 {
+                return Wake::Input;
+            }
+
+            ReadConsoleInputW(stdin, &mut input as *mut InputRecord, 1, &mut entries_read as *mut u32);
+            if input.event_type == WINDOW_BUFFER_SIZE_EVENT {
+                RESIZE_PENDING.store(true, Ordering::Relaxed);
+                return Wake::Resize;
+            }
+        }
+    }
+}
+
+/// Returns true once after every resize seen by `wait_input`.
+pub(crate) fn resize_take() -> bool {
+    return RESIZE_PENDING.swap(false, Ordering::Relaxed);
+}
+
+/// Returns true when the console understands escape sequences, false for the legacy console.
+pub(crate) fn ansi_supported() -> bool {
+    return unsafe { supports_ansi };
+}
+
+static RESIZE_PENDING: AtomicBool = AtomicBool::new(false);
+
+// NOTE: The legacy console always sends focus records, they are only reported once enabled.
+static FOCUS_REPORT: AtomicBool = AtomicBool::new(false);
+
+fn modifiers_legacy(control_key_state: u32) -> Modifiers {
+    let mut modifiers = Modifiers::NONE;
+    if control_key_state & (RIGHT_ALT_PRESSED | LEFT_ALT_PRESSED) != 0 {
+        modifiers |= Modifiers::ALT;
+    }
+
+    if control_key_state & (RIGHT_CTRL_PRESSED | LEFT_CTRL_PRESSED) != 0 {
+        modifiers |= Modifiers::CTRL;
+    }
+
+    if control_key_state & SHIFT_PRESSED != 0 {
+        modifiers |= Modifiers::SHIFT;
+    }
+
+    return modifiers;
+}
+
+/// Same as `read_event_legacy`, skipping over everything that is not a key.
+unsafe fn read_key_legacy(timeout: Option<Duration>) -> Result<Option<KeyEvent>> {
+    let start = Instant::now();
+    loop {
+        let remaining = timeout.map(|timeout| timeout.saturating_sub(start.elapsed()));
+        match read_event_legacy(remaining)? {
+            Some(Event::Key(key)) => return Ok(Some(key)),
+            Some(_) => continue,
+            None => return Ok(None),
         }
     }
 }
 
-unsafe fn read_key_legacy() -> KeyCode {
+unsafe fn read_event_legacy(timeout: Option<Duration>) -> Result<Option<Event>> {
     let mut entries_read = 0u32;
     let mut input = InputRecord::default();
+    let start = Instant::now();
     let key = loop {
+        if timeout.is_some() {
+            let remaining = timeout.map(|timeout| timeout.saturating_sub(start.elapsed()));
+            if wait_input(remaining) == Wake::Timeout {
+                return Ok(None);
+            }
+        }
+
         let result = ReadConsoleInputW(stdin, &mut input as *mut InputRecord, 1, &mut entries_read as *mut u32);
 
         // Reading the console input failed.
         if result == 0 || entries_read == 0 {
-            return fallback_read_key();
+            return Ok(Some(Event::Key(fallback_read_key()?)));
+        }
+
+        if input.event_type == MOUSE_EVENT {
+            match mouse_legacy(input.event.mouse) {
+                Some(event) => break event,
+                None => continue,
+            }
+        }
+
+        if input.event_type == WINDOW_BUFFER_SIZE_EVENT {
+            break Event::Resize(window_size()?.size);
+        }
+
+        if input.event_type == FOCUS_EVENT {
+            if !FOCUS_REPORT.load(Ordering::Relaxed) {
+                continue;
+            }
+
+            match input.event.focus {
+                0 => break Event::FocusLost,
+                _ => break Event::FocusGained,
+            }
         }
 
-        if input.event_type != 1 {
+        if input.event_type != KEY_EVENT {
             continue;
         }
 
@@ -206,229 +585,1023 @@ unsafe fn read_key_legacy() -> KeyCode {
         }
 
         let key = input.event.key;
+        let mut modifiers = modifiers_legacy(key.control_key_state);
+
+        // NOTE: AltGr is reported as Left Ctrl together with Right Alt. When it produces a
+        //       character (e.g. `@` or `€` on a German layout), it is that character being typed.
+        let altgr = LEFT_CTRL_PRESSED | RIGHT_ALT_PRESSED;
+        if key.control_key_state & altgr == altgr && key.character_data >= 0x21 {
+            modifiers.remove(Modifiers::CTRL | Modifiers::ALT);
+        }
+
+        // Ctrl+letter arrives as a control character, report the letter instead.
+        if modifiers.contains(Modifiers::CTRL) && (0x41..=0x5a).contains(&key.virtual_keycode) {
+            let letter = (key.virtual_keycode as u8).to_ascii_lowercase() as char;
+            break Event::Key(KeyEvent::new(KeyCode::Char(letter), modifiers));
+        }
 
         if key.character_data != 0 {
             let data = key.character_data;
-            match key.character_data as u8 {
-                b'0'..=b'9' => break KeyCode::Char(char::from_u32_unchecked(data as u32)),
-                b'a'..=b'z' => break KeyCode::Char(char::from_u32_unchecked(data as u32)),
-                b'A'..=b'Z' => break KeyCode::Char(char::from_u32_unchecked(data as u32)),
-                8  => break KeyCode::Backspace,
-                13 => break KeyCode::Enter,
-                32 => break KeyCode::Space,
-                _  => {}
-                // _  => break KeyCode::Other(data as u64),
+            let code = match data {
+                8  => KeyCode::Backspace,
+                9 if modifiers.contains(Modifiers::SHIFT) => KeyCode::BackTab,
+                9  => KeyCode::Tab,
+                13 => KeyCode::Enter,
+                27 => KeyCode::Escape,
+                32 => KeyCode::Space,
+                // Characters outside of the BMP are delivered as two separate key events.
+                0xd800..=0xdbff => {
+                    HIGH_SURROGATE.store(data, Ordering::Relaxed);
+                    continue;
+                }
+                0xdc00..=0xdfff => {
+                    let pair = [HIGH_SURROGATE.swap(0, Ordering::Relaxed), data];
+
+                    match char::decode_utf16(pair).next() {
+                        Some(Ok(character)) => KeyCode::Char(character),
+                        _ => continue,
+                    }
+                }
+                0x21.. => KeyCode::Char(char::from_u32_unchecked(data as u32)),
+                _  => continue,
+                // _  => KeyCode::Other(data as u64),
+            };
+
+            // Shift is already applied to the character itself.
+            let mut modifiers = modifiers;
+            if let KeyCode::Char(_) = code {
+                modifiers.remove(Modifiers::SHIFT);
             }
+
+            break Event::Key(KeyEvent::new(code, modifiers));
         }
 
-        match key.virtual_keycode {
-            0x25 => break KeyCode::ArrowLeft,
-            0x26 => break KeyCode::ArrowUp,
-            0x27 => break KeyCode::ArrowRight,
-            0x28 => break KeyCode::ArrowDown,
+        let code = match key.virtual_keycode {
+            0x0c => KeyCode::Begin,
+            0x21 => KeyCode::PageUp,
+            0x22 => KeyCode::PageDown,
+            0x23 => KeyCode::End,
+            0x24 => KeyCode::Home,
+            0x25 => KeyCode::ArrowLeft,
+            0x26 => KeyCode::ArrowUp,
+            0x27 => KeyCode::ArrowRight,
+            0x28 => KeyCode::ArrowDown,
+            0x2d => KeyCode::Insert,
+            0x2e => KeyCode::Delete,
+            0x70..=0x83 => KeyCode::F((key.virtual_keycode - 0x70 + 1) as u8),
             _    => continue,
-        }
+        };
+
+        break Event::Key(KeyEvent::new(code, modifiers));
     };
 
-    return key;
+    return Ok(Some(key));
 }
 
-pub fn print_str(string: &str) -> isize {
-    unsafe {
-        let mut bytes_written: u32 = 0;
-        let result = WriteConsoleA(
-            stdout,
-            string.as_ptr() as *const void,
-            string.len() as u32,
-            &mut bytes_written as *mut u32,
-            std::ptr::null()
-        );
+fn mouse_button_legacy(button_state: u32) -> MouseButton {
+    if button_state & FROM_LEFT_1ST_BUTTON_PRESSED != 0 {
+        return MouseButton::Left;
+    }
 
-        if result == 0 {
-            return -1;
-        }
+    if button_state & RIGHTMOST_BUTTON_PRESSED != 0 {
+        return MouseButton::Right;
+    }
+
+    if button_state & FROM_LEFT_2ND_BUTTON_PRESSED != 0 {
+        return MouseButton::Middle;
+    }
+
+    if button_state & FROM_LEFT_3RD_BUTTON_PRESSED != 0 {
+        return MouseButton::Back;
+    }
 
-        return bytes_written as isize;
+    if button_state & FROM_LEFT_4TH_BUTTON_PRESSED != 0 {
+        return MouseButton::Forward;
     }
+
+    return MouseButton::None;
 }
 
-pub fn print_buf(buffer: &[u8], buffer_size: usize) -> isize {
-    unsafe {
-        let mut bytes_written: u32 = 0;
-        let result = WriteConsoleA(
-            stdout,
-            buffer.as_ptr() as *const void,
-            buffer_size as u32,
-            &mut bytes_written as *mut u32,
-            std::ptr::null()
-        );
+unsafe fn mouse_legacy(mouse: EventMouse) -> Option<Event> {
+    let mode = (*MOUSE_MODE.lock().unwrap_or_else(|error| error.into_inner()))?;
 
-        if result == 0 {
-            return -1;
-        }
+    let modifiers = modifiers_legacy(mouse.control_key_state);
+    let pos = Pos {
+        x: mouse.mouse_position.x.max(0) as u16,
+        y: mouse.mouse_position.y.max(0) as u16,
+    };
 
-        return bytes_written as isize;
+    // High word of the button state holds the signed wheel delta.
+    let wheel_delta = (mouse.button_state >> 16) as i16;
+    if mouse.event_flags & MOUSE_WHEELED != 0 {
+        let kind = if wheel_delta > 0 { MouseKind::ScrollUp } else { MouseKind::ScrollDown };
+        return Some(Event::Mouse { kind, button: MouseButton::None, pos, modifiers });
     }
-}
 
-pub fn read_buf(buffer: &mut [u8]) -> isize {
-    unsafe {
-        let mut bytes_read = 0u32;
-        let result = ReadConsoleA(
-            stdin,
-            buffer.as_mut_ptr() as *mut void,
-            buffer.len() as u32,
-            &mut bytes_read as *mut u32,
-            std::ptr::null()
-        );
+    if mouse.event_flags & MOUSE_HWHEELED != 0 {
+        let kind = if wheel_delta > 0 { MouseKind::ScrollRight } else { MouseKind::ScrollLeft };
+        return Some(Event::Mouse { kind, button: MouseButton::None, pos, modifiers });
+    }
 
-        if result == 0 {
-            return -1;
+    // The console only reports which buttons are currently down, presses and releases are
+    // found by comparing against the previous state.
+    let buttons  = mouse.button_state & 0xffff;
+    let previous = MOUSE_BUTTONS.swap(buttons, Ordering::Relaxed);
+    let pressed  = buttons & !previous;
+    let released = previous & !buttons;
+
+    let (kind, button) = if pressed != 0 {
+        (MouseKind::Press, mouse_button_legacy(pressed))
+    } else if released != 0 {
+        (MouseKind::Release, mouse_button_legacy(released))
+    } else if mouse.event_flags & MOUSE_MOVED != 0 && buttons != 0 {
+        if mode == MouseMode::Click {
+            return None;
         }
 
-        return bytes_read as isize;
-    }
+        (MouseKind::Drag, mouse_button_legacy(buttons))
+    } else if mouse.event_flags & MOUSE_MOVED != 0 {
+        if mode != MouseMode::Motion {
+            return None;
+        }
+
+        (MouseKind::Move, MouseButton::None)
+    } else {
+        return None;
+    };
+
+    return Some(Event::Mouse { kind, button, pos, modifiers });
 }
 
-pub fn console_clear() {
+/// Makes the console report mouse input, delivered as `Event::Mouse` by `read_event`.
+pub fn mouse_enable(mode: MouseMode) -> Result<()> {
     unsafe {
+        let mut input_mode = 0u32;
+        if GetConsoleMode(stdin, &mut input_mode as *mut u32) == 0 {
+            return Err(last_error());
+        }
+
+        // Quick edit mode takes over the mouse for text selection.
+        input_mode |= ENABLE_MOUSE_INPUT | ENABLE_EXTENDED_FLAGS;
+        input_mode &= !ENABLE_QUICK_EDIT_MODE;
+        if SetConsoleMode(stdin, input_mode) == 0 {
+            return Err(last_error());
+        }
+
+        *MOUSE_MODE.lock().unwrap_or_else(|error| error.into_inner()) = Some(mode);
+
         if supports_ansi {
-            ansi::console_clear();
-        } else {
-            console_clear_legacy();
+            return ansi::print_with(|out| ansi::mouse_enable(out, mode));
         }
+
+        return Ok(());
     }
 }
 
-unsafe fn console_clear_legacy() {
-    let mut buffer_info = ConsoleBufferInfo::default();
-    let result = GetConsoleScreenBufferInfo(stdout, &mut buffer_info as *mut ConsoleBufferInfo);
-    if result == 0 {
-        return;
-    }
+pub fn mouse_disable() -> Result<()> {
+    unsafe {
+        let mut input_mode = 0u32;
+        if GetConsoleMode(stdin, &mut input_mode as *mut u32) == 0 {
+            return Err(last_error());
+        }
 
-    let rect = SmallRect {
-        left:   0,
-        top:    0,
-        right:  buffer_info.buffer_size.x,
-        bottom: buffer_info.buffer_size.y,
-    };
+        input_mode &= !ENABLE_MOUSE_INPUT;
+        input_mode |= ENABLE_QUICK_EDIT_MODE | ENABLE_EXTENDED_FLAGS;
+        if SetConsoleMode(stdin, input_mode) == 0 {
+            return Err(last_error());
+        }
 
-    let target = Coord {
-        x: 0,
-        y: 0 - buffer_info.buffer_size.y
-    };
+        *MOUSE_MODE.lock().unwrap_or_else(|error| error.into_inner()) = None;
+        MOUSE_BUTTONS.store(0, Ordering::Relaxed);
 
-    let fill = CharInfo {
-        unicode_char: 32,
-        attributes:   buffer_info.attributes,
-    };
+        if supports_ansi {
+            return ansi::print_with(ansi::mouse_disable);
+        }
 
-    let result = ScrollConsoleScreenBufferW(stdout, &rect as *const SmallRect, std::ptr::null(), target, &fill as *const CharInfo);
-    if result == 0 {
-        return;
+        return Ok(());
     }
-
-    buffer_info.cursor_position.x = 0;
-    buffer_info.cursor_position.y = 0;
-    SetConsoleCursorPosition(stdout, buffer_info.cursor_position);
 }
 
-pub fn cursor_get() -> Pos {
+/// Switches to the alternate screen, which full screen applications draw on so that the
+/// console output is shown again once they are done. See also `RawConfig::alternate_screen`.
+pub fn alternate_screen_enter() -> Result<()> {
     unsafe {
-        if supports_ansi {
-            return ansi::cursor_get();
-        } else {
-            return cursor_get_legacy();
+        if !supports_ansi {
+            return Err(Error::Unsupported("alternate screen"));
         }
+
+        return ansi::print_with(ansi::alternate_screen_enter);
     }
 }
 
-unsafe fn cursor_get_legacy() -> Pos {
-    let mut buffer_info = ConsoleBufferInfo::default();
-    let _ = GetConsoleScreenBufferInfo(stdout, &mut buffer_info as *mut ConsoleBufferInfo);
-
-    let pos = Pos {
-        x: buffer_info.cursor_position.x as u16,
-        y: buffer_info.cursor_position.y as u16,
-    };
+pub fn alternate_screen_leave() -> Result<()> {
+    unsafe {
+        if !supports_ansi {
+            return Ok(());
+        }
 
-    return pos;
+        return ansi::print_with(ansi::alternate_screen_leave);
+    }
 }
 
-pub fn cursor_set(x: u16, y: u16) {
+/// Makes the terminal mark pasted text, delivered as a single `Event::Paste` by `read_event`.
+// NOTE: The legacy console delivers pastes as regular key presses.
+pub fn paste_enable() -> Result<()> {
     unsafe {
-        if supports_ansi {
-            return ansi::cursor_set(x, y);
-        } else {
-            return cursor_set_legacy(x as i16, y as i16);
+        if !supports_ansi {
+            return Err(Error::Unsupported("bracketed paste"));
         }
-    }
-}
 
-unsafe fn cursor_set_legacy(x: i16, y: i16) {
-    let position = Coord { x, y };
-    SetConsoleCursorPosition(stdout, position);
+        return ansi::print_with(ansi::paste_enable);
+    }
 }
 
-pub fn color_bg(red: u8, green: u8, blue: u8) {
+pub fn paste_disable() -> Result<()> {
     unsafe {
-        if supports_ansi {
-            ansi::color_bg(red, green, blue);
-        } else {
-            // Not supported. Fallback to legacy windows console colors?
+        if !supports_ansi {
+            return Ok(());
         }
+
+        return ansi::print_with(ansi::paste_disable);
     }
 }
 
-pub fn color_fg(red: u8, green: u8, blue: u8) {
+/// Makes the console report focus changes, delivered as `Event::FocusGained` and
+/// `Event::FocusLost` by `read_event`.
+pub fn focus_enable() -> Result<()> {
+    FOCUS_REPORT.store(true, Ordering::Relaxed);
     unsafe {
         if supports_ansi {
-            ansi::color_fg(red, green, blue);
-        } else {
-            // Not supported. Fallback to legacy windows console colors?
+            return ansi::print_with(ansi::focus_enable);
         }
     }
+
+    return Ok(());
 }
 
-pub fn color_reset() {
+pub fn focus_disable() -> Result<()> {
+    FOCUS_REPORT.store(false, Ordering::Relaxed);
     unsafe {
         if supports_ansi {
-            ansi::color_reset();
-        } else {
-            color_reset_legacy();
+            return ansi::print_with(ansi::focus_disable);
         }
     }
+
+    return Ok(());
 }
 
-unsafe fn color_reset_legacy() {
-    let color_white = 15;
-    SetConsoleTextAttribute(stdout, color_white);
+pub fn print_str(string: &str) -> Result<usize> {
+    return print_buf(string.as_bytes(), string.len());
 }
 
-pub fn buffer_size() -> Pos {
-    unsafe {
-        if supports_ansi {
-            let prev = cursor_get();
+pub fn print_buf(buffer: &[u8], buffer_size: usize) -> Result<usize> {
+    let buffer = &buffer[..buffer_size.min(buffer.len())];
 
-            cursor_set(u16::MAX, u16::MAX);
+    let mut total_written = 0;
+    while total_written < buffer.len() {
+        let remaining = &buffer[total_written..];
+        let mut bytes_written: u32 = 0;
+        let result = unsafe {
+            WriteConsoleA(
+                stdout,
+                remaining.as_ptr() as *const void,
+                remaining.len().min(u32::MAX as usize) as u32,
+                &mut bytes_written as *mut u32,
+                std::ptr::null()
+            )
+        };
 
-            let mut size = cursor_get();
-            size.x = size.x.saturating_add(1);
-            size.y = size.y.saturating_add(1);
+        if result == 0 {
+            return Err(last_error());
+        }
 
-            cursor_set(prev.x, prev.y);
+        total_written += bytes_written as usize;
+    }
 
-            return size;
-        } else {
+    return Ok(total_written);
+}
+
+pub fn read_buf(buffer: &mut [u8]) -> Result<usize> {
+    unsafe {
+        let mut bytes_read = 0u32;
+        let result = ReadConsoleA(
+            stdin,
+            buffer.as_mut_ptr() as *mut void,
+            buffer.len().min(u32::MAX as usize) as u32,
+            &mut bytes_read as *mut u32,
+            std::ptr::null()
+        );
+
+        if result == 0 {
+            return Err(last_error());
+        }
+
+        return Ok(bytes_read as usize);
+    }
+}
+
+pub fn console_clear() -> Result<()> {
+    unsafe {
+        if supports_ansi {
+            return ansi::print_with(ansi::console_clear);
+        } else {
+            return console_clear_legacy();
+        }
+    }
+}
+
+/// Erases part of the screen, see `ClearType`. The cursor stays where it is.
+pub fn console_erase(clear_type: ClearType) -> Result<()> {
+    unsafe {
+        if supports_ansi {
+            return ansi::print_with(|out| ansi::console_erase(out, clear_type));
+        } else {
+            return console_erase_legacy(clear_type);
+        }
+    }
+}
+
+unsafe fn console_erase_legacy(clear_type: ClearType) -> Result<()> {
+    let mut buffer_info = ConsoleBufferInfo::default();
+    if GetConsoleScreenBufferInfo(stdout, &mut buffer_info as *mut ConsoleBufferInfo) == 0 {
+        return Err(last_error());
+    }
+
+    let width  = buffer_info.buffer_size.x as u32;
+    let cursor = buffer_info.cursor_position;
+    let window = buffer_info.window_coords;
+
+    // NOTE: The cursor can be scrolled out of the window, the screen is only erased up to
+    //       the edge of the window then.
+    let screen_cursor = Coord { x: cursor.x, y: cursor.y.clamp(window.top, window.bottom) };
+
+    // NOTE: Cells are filled in one run, wrapping from the end of one line to the next.
+    let (start, length) = match clear_type {
+        ClearType::LineEnd     => (cursor, width - cursor.x as u32),
+        ClearType::LineStart   => (Coord { x: 0, y: cursor.y }, cursor.x as u32 + 1),
+        ClearType::Line        => (Coord { x: 0, y: cursor.y }, width),
+        ClearType::ScreenEnd   => (screen_cursor, width - cursor.x as u32 + width * (window.bottom - screen_cursor.y) as u32),
+        ClearType::ScreenStart => (Coord { x: 0, y: window.top }, width * (screen_cursor.y - window.top) as u32 + cursor.x as u32 + 1),
+        ClearType::Screen      => (Coord { x: 0, y: window.top }, width * (window.bottom - window.top + 1) as u32),
+        ClearType::Scrollback  => (Coord { x: 0, y: 0 }, width * window.top as u32),
+        ClearType::Characters(count) => (cursor, (count as u32).min(width - cursor.x as u32)),
+    };
+
+    let mut written = 0u32;
+    if FillConsoleOutputCharacterW(stdout, b' ' as u16, length, start, &mut written as *mut u32) == 0 {
+        return Err(last_error());
+    }
+
+    if FillConsoleOutputAttribute(stdout, buffer_info.attributes, length, start, &mut written as *mut u32) == 0 {
+        return Err(last_error());
+    }
+
+    return Ok(());
+}
+
+unsafe fn console_clear_legacy() -> Result<()> {
+    let mut buffer_info = ConsoleBufferInfo::default();
+    let result = GetConsoleScreenBufferInfo(stdout, &mut buffer_info as *mut ConsoleBufferInfo);
+    if result == 0 {
+        return Err(last_error());
+    }
+
+    let rect = SmallRect {
+        left:   0,
+        top:    0,
+        right:  buffer_info.buffer_size.x,
+        bottom: buffer_info.buffer_size.y,
+    };
+
+    let target = Coord {
+        x: 0,
+        y: 0 - buffer_info.buffer_size.y
+    };
+
+    let fill = CharInfo {
+        unicode_char: 32,
+        attributes:   buffer_info.attributes,
+    };
+
+    let result = ScrollConsoleScreenBufferW(stdout, &rect as *const SmallRect, std::ptr::null(), target, &fill as *const CharInfo);
+    if result == 0 {
+        return Err(last_error());
+    }
+
+    buffer_info.cursor_position.x = 0;
+    buffer_info.cursor_position.y = 0;
+    if SetConsoleCursorPosition(stdout, buffer_info.cursor_position) == 0 {
+        return Err(last_error());
+    }
+
+    return Ok(());
+}
+
+/// Moves the cursor up by `count` lines, stopping at the top of the screen.
+pub fn cursor_up(count: u16) -> Result<()> {
+    unsafe {
+        if supports_ansi {
+            return ansi::print_with(|out| ansi::cursor_up(out, count));
+        } else {
+            return cursor_move_legacy(0, -(count as i32), None);
+        }
+    }
+}
+
+pub fn cursor_down(count: u16) -> Result<()> {
+    unsafe {
+        if supports_ansi {
+            return ansi::print_with(|out| ansi::cursor_down(out, count));
+        } else {
+            return cursor_move_legacy(0, count as i32, None);
+        }
+    }
+}
+
+pub fn cursor_left(count: u16) -> Result<()> {
+    unsafe {
+        if supports_ansi {
+            return ansi::print_with(|out| ansi::cursor_left(out, count));
+        } else {
+            return cursor_move_legacy(-(count as i32), 0, None);
+        }
+    }
+}
+
+pub fn cursor_right(count: u16) -> Result<()> {
+    unsafe {
+        if supports_ansi {
+            return ansi::print_with(|out| ansi::cursor_right(out, count));
+        } else {
+            return cursor_move_legacy(count as i32, 0, None);
+        }
+    }
+}
+
+/// Moves the cursor to the start of the line `count` lines down.
+pub fn cursor_line_next(count: u16) -> Result<()> {
+    // Stay on the current column, same as the escape sequence does.
+    if count == 0 {
+        return Ok(());
+    }
+
+    unsafe {
+        if supports_ansi {
+            return ansi::print_with(|out| ansi::cursor_line_next(out, count));
+        } else {
+            return cursor_move_legacy(0, count as i32, Some(0));
+        }
+    }
+}
+
+/// Moves the cursor to the start of the line `count` lines up.
+pub fn cursor_line_prev(count: u16) -> Result<()> {
+    // Stay on the current column, same as the escape sequence does.
+    if count == 0 {
+        return Ok(());
+    }
+
+    unsafe {
+        if supports_ansi {
+            return ansi::print_with(|out| ansi::cursor_line_prev(out, count));
+        } else {
+            return cursor_move_legacy(0, -(count as i32), Some(0));
+        }
+    }
+}
+
+/// Moves the cursor to column `x` of the current line.
+pub fn cursor_column(x: u16) -> Result<()> {
+    unsafe {
+        if supports_ansi {
+            return ansi::print_with(|out| ansi::cursor_column(out, x));
+        } else {
+            return cursor_move_legacy(0, 0, Some(x));
+        }
+    }
+}
+
+/// Remembers the cursor position, to go back to it with `cursor_restore`.
+pub fn cursor_save() -> Result<()> {
+    unsafe {
+        if supports_ansi {
+            return ansi::print_with(ansi::cursor_save);
+        } else {
+            return cursor_save_legacy();
+        }
+    }
+}
+
+pub fn cursor_restore() -> Result<()> {
+    unsafe {
+        if supports_ansi {
+            return ansi::print_with(ansi::cursor_restore);
+        } else {
+            return cursor_restore_legacy();
+        }
+    }
+}
+
+pub fn cursor_hide() -> Result<()> {
+    unsafe {
+        if supports_ansi {
+            return ansi::print_with(ansi::cursor_hide);
+        } else {
+            return cursor_visible_legacy(false);
+        }
+    }
+}
+
+pub fn cursor_show() -> Result<()> {
+    unsafe {
+        if supports_ansi {
+            return ansi::print_with(ansi::cursor_show);
+        } else {
+            return cursor_visible_legacy(true);
+        }
+    }
+}
+
+/// Moves the cursor relative to where it is, or to `column` when given, clamped to the
+/// screen buffer.
+unsafe fn cursor_move_legacy(x: i32, y: i32, column: Option<u16>) -> Result<()> {
+    let mut buffer_info = ConsoleBufferInfo::default();
+    if GetConsoleScreenBufferInfo(stdout, &mut buffer_info as *mut ConsoleBufferInfo) == 0 {
+        return Err(last_error());
+    }
+
+    let size   = buffer_info.buffer_size;
+    let cursor = buffer_info.cursor_position;
+
+    let new_x = match column {
+        Some(column) => column as i32,
+        None => cursor.x as i32 + x,
+    };
+    let new_y = cursor.y as i32 + y;
+
+    let position = Coord {
+        x: new_x.clamp(0, size.x as i32 - 1) as i16,
+        y: new_y.clamp(0, size.y as i32 - 1) as i16,
+    };
+
+    if SetConsoleCursorPosition(stdout, position) == 0 {
+        return Err(last_error());
+    }
+
+    return Ok(());
+}
+
+unsafe fn cursor_save_legacy() -> Result<()> {
+    let pos = cursor_get_legacy()?;
+    *CURSOR_SAVED.lock().unwrap_or_else(|error| error.into_inner()) = Some(pos);
+    return Ok(());
+}
+
+unsafe fn cursor_restore_legacy() -> Result<()> {
+    let saved = *CURSOR_SAVED.lock().unwrap_or_else(|error| error.into_inner());
+
+    // Same as terminals, restoring without a saved position goes to the top left corner.
+    let pos = saved.unwrap_or_default();
+    return cursor_set_legacy(pos.x as i16, pos.y as i16);
+}
+
+unsafe fn cursor_visible_legacy(visible: bool) -> Result<()> {
+    let mut cursor_info = CursorInfo::default();
+    if GetConsoleCursorInfo(stdout, &mut cursor_info as *mut CursorInfo) == 0 {
+        return Err(last_error());
+    }
+
+    cursor_info.visible = visible as i32;
+    if SetConsoleCursorInfo(stdout, &cursor_info as *const CursorInfo) == 0 {
+        return Err(last_error());
+    }
+
+    return Ok(());
+}
+
+/// Changes the look of the cursor. A changed shape is reset when the `RawMode` guard is
+/// dropped or the program panics.
+// NOTE: The legacy console only knows the cursor height, blinking and bars are not supported.
+pub fn cursor_shape(shape: CursorShape) -> Result<()> {
+    unsafe {
+        if supports_ansi {
+            return ansi::print_with(|out| ansi::cursor_shape(out, shape));
+        }
+
+        let size = match shape {
+            CursorShape::Default => 25,
+            CursorShape::BlinkingBlock | CursorShape::SteadyBlock => 100,
+            CursorShape::BlinkingUnderline | CursorShape::SteadyUnderline => 25,
+            CursorShape::BlinkingBar | CursorShape::SteadyBar => {
+                return Err(Error::Unsupported("bar cursor"));
+            }
+        };
+
+        let mut cursor_info = CursorInfo::default();
+        if GetConsoleCursorInfo(stdout, &mut cursor_info as *mut CursorInfo) == 0 {
+            return Err(last_error());
+        }
+
+        cursor_info.size = size;
+        if SetConsoleCursorInfo(stdout, &cursor_info as *const CursorInfo) == 0 {
+            return Err(last_error());
+        }
+
+        return Ok(());
+    }
+}
+
+pub fn cursor_get() -> Result<Pos> {
+    unsafe {
+        if supports_ansi {
+            return ansi::cursor_get();
+        } else {
+            return cursor_get_legacy();
+        }
+    }
+}
+
+unsafe fn cursor_get_legacy() -> Result<Pos> {
+    let mut buffer_info = ConsoleBufferInfo::default();
+    if GetConsoleScreenBufferInfo(stdout, &mut buffer_info as *mut ConsoleBufferInfo) == 0 {
+        return Err(last_error());
+    }
+
+    let pos = Pos {
+        x: buffer_info.cursor_position.x as u16,
+        y: buffer_info.cursor_position.y as u16,
+    };
+
+    return Ok(pos);
+}
+
+pub fn cursor_set(x: u16, y: u16) -> Result<()> {
+    unsafe {
+        if supports_ansi {
+            return ansi::print_with(|out| ansi::cursor_set(out, x, y));
+        } else {
+            return cursor_set_legacy(x as i16, y as i16);
+        }
+    }
+}
+
+unsafe fn cursor_set_legacy(x: i16, y: i16) -> Result<()> {
+    let position = Coord { x, y };
+    if SetConsoleCursorPosition(stdout, position) == 0 {
+        return Err(last_error());
+    }
+
+    return Ok(());
+}
+
+pub fn color_bg(red: u8, green: u8, blue: u8) -> Result<()> {
+    unsafe {
+        if supports_ansi {
+            return ansi::print_with(|out| ansi::color_bg(out, color::style_enabled(), red, green, blue));
+        } else {
+            return color_set_legacy(Color::Rgb { red, green, blue }, true);
+        }
+    }
+}
+
+pub fn color_fg(red: u8, green: u8, blue: u8) -> Result<()> {
+    unsafe {
+        if supports_ansi {
+            return ansi::print_with(|out| ansi::color_fg(out, color::style_enabled(), red, green, blue));
+        } else {
+            return color_set_legacy(Color::Rgb { red, green, blue }, false);
+        }
+    }
+}
+
+pub fn color_reset() -> Result<()> {
+    unsafe {
+        if supports_ansi {
+            return ansi::print_with(|out| ansi::color_reset(out, color::style_enabled()));
+        } else {
+            return color_reset_legacy();
+        }
+    }
+}
+
+unsafe fn color_reset_legacy() -> Result<()> {
+    if !color::style_enabled() {
+        return Ok(());
+    }
+
+    let color_white = 15;
+    if SetConsoleTextAttribute(stdout, color_white) == 0 {
+        return Err(last_error());
+    }
+
+    return Ok(());
+}
+
+/// Sets the colour of underlines, independent of the text colour.
+pub fn color_underline(red: u8, green: u8, blue: u8) -> Result<()> {
+    unsafe {
+        if supports_ansi {
+            return ansi::print_with(|out| ansi::color_underline(out, color::style_enabled(), red, green, blue));
+        } else {
+            return Err(Error::Unsupported("underline colors"));
+        }
+    }
+}
+
+/// Returns how many colours the terminal can show, as detected by `terma_init`. Colours are
+/// downsampled to the closest ones available.
+pub fn color_depth() -> ColorDepth {
+    return color::depth();
+}
+
+/// Overrides the detected colour depth.
+pub fn color_depth_set(depth: ColorDepth) {
+    color::depth_set(depth);
+}
+
+/// Asks the terminal itself whether it shows 24-bit colours, for terminals which do not
+/// advertise it through the environment. Resets the background colour.
+pub fn color_depth_query() -> Result<ColorDepth> {
+    unsafe {
+        if !supports_ansi {
+            return Ok(color::depth());
+        }
+
+        return ansi::color_depth_query();
+    }
+}
+
+/// Returns the policy deciding whether colours and text attributes are sent.
+pub fn style_policy() -> StylePolicy {
+    return color::style_policy();
+}
+
+pub fn style_policy_set(policy: StylePolicy) {
+    color::style_policy_set(policy);
+}
+
+/// Returns true when colours and text attributes are sent under the current policy. Colour
+/// changes are skipped as well when `color_depth` is `ColorDepth::None`.
+pub fn style_enabled() -> bool {
+    return color::style_enabled();
+}
+
+pub fn color_fg_set(color: Color) -> Result<()> {
+    unsafe {
+        if supports_ansi {
+            return ansi::print_with(|out| ansi::color_fg_set(out, color::style_enabled(), color));
+        } else {
+            return color_set_legacy(color, false);
+        }
+    }
+}
+
+pub fn color_bg_set(color: Color) -> Result<()> {
+    unsafe {
+        if supports_ansi {
+            return ansi::print_with(|out| ansi::color_bg_set(out, color::style_enabled(), color));
+        } else {
+            return color_set_legacy(color, true);
+        }
+    }
+}
+
+pub fn color_underline_set(color: Color) -> Result<()> {
+    unsafe {
+        if supports_ansi {
+            return ansi::print_with(|out| ansi::color_underline_set(out, color::style_enabled(), color));
+        } else {
+            return Err(Error::Unsupported("underline colors"));
+        }
+    }
+}
+
+// NOTE: The legacy console only has the 16 named colours, everything else is mapped onto them.
+unsafe fn color_set_legacy(color: Color, background: bool) -> Result<()> {
+    if !color::style_enabled() {
+        return Ok(());
+    }
+
+    let depth = color::depth().min(ColorDepth::Ansi16);
+    let Some(color) = color::downsample(color, depth) else {
+        return Ok(());
+    };
+
+    let index = match color {
+        Color::Default if background => 0,
+        Color::Default => 7,
+        _ => color.index().unwrap_or_default() as u16,
+    };
+
+    // Palette order is red, green, blue from the lowest bit, the console has it the other way around.
+    let console_color = (index & 0b1000) | ((index & 0b001) << 2) | (index & 0b010) | ((index & 0b100) >> 2);
+
+    let mut buffer_info = ConsoleBufferInfo::default();
+    if GetConsoleScreenBufferInfo(stdout, &mut buffer_info as *mut ConsoleBufferInfo) == 0 {
+        return Err(last_error());
+    }
+
+    let text_attributes = match background {
+        true  => (buffer_info.attributes & !0x00f0) | (console_color << 4),
+        false => (buffer_info.attributes & !0x000f) | console_color,
+    };
+
+    if SetConsoleTextAttribute(stdout, text_attributes) == 0 {
+        return Err(last_error());
+    }
+
+    return Ok(());
+}
+
+/// Turns on the given text attributes, keeping the ones already active.
+pub fn attributes_set(attributes: Attributes) -> Result<()> {
+    unsafe {
+        if supports_ansi {
+            return ansi::print_with(|out| ansi::attributes_set(out, color::style_enabled(), attributes));
+        } else {
+            return attributes_legacy(attributes, true);
+        }
+    }
+}
+
+/// Turns off the given text attributes without resetting colours.
+pub fn attributes_clear(attributes: Attributes) -> Result<()> {
+    unsafe {
+        if supports_ansi {
+            return ansi::print_with(|out| ansi::attributes_clear(out, color::style_enabled(), attributes));
+        } else {
+            return attributes_legacy(attributes, false);
+        }
+    }
+}
+
+// NOTE: The legacy console only has bright text (used for bold), underlines and reverse video.
+unsafe fn attributes_legacy(attributes: Attributes, enable: bool) -> Result<()> {
+    if !color::style_enabled() {
+        return Ok(());
+    }
+
+    // Turning off something that can not be turned on in the first place is fine.
+    if enable {
+        let mut unsupported = attributes;
+        unsupported.remove(Attributes::BOLD | Attributes::REVERSE | Attributes::UNDERLINES);
+        if !unsupported.is_empty() {
+            return Err(Error::Unsupported("text attribute"));
+        }
+    }
+
+    let mut buffer_info = ConsoleBufferInfo::default();
+    if GetConsoleScreenBufferInfo(stdout, &mut buffer_info as *mut ConsoleBufferInfo) == 0 {
+        return Err(last_error());
+    }
+
+    let mut flags = 0;
+    if attributes.contains(Attributes::BOLD) {
+        flags |= FOREGROUND_INTENSITY;
+    }
+
+    if attributes.contains(Attributes::REVERSE) {
+        flags |= COMMON_LVB_REVERSE_VIDEO;
+    }
+
+    if attributes.intersects(Attributes::UNDERLINES) {
+        flags |= COMMON_LVB_UNDERSCORE;
+    }
+
+    let text_attributes = match enable {
+        true  => buffer_info.attributes | flags,
+        false => buffer_info.attributes & !flags,
+    };
+
+    if SetConsoleTextAttribute(stdout, text_attributes) == 0 {
+        return Err(last_error());
+    }
+
+    return Ok(());
+}
+
+pub fn buffer_size() -> Result<Pos> {
+    unsafe {
+        if supports_ansi {
+            return Ok(window_size()?.size);
+        } else {
             let mut buffer_info = ConsoleBufferInfo::default();
-            let _ = GetConsoleScreenBufferInfo(stdout, &mut buffer_info as *mut ConsoleBufferInfo);
+            if GetConsoleScreenBufferInfo(stdout, &mut buffer_info as *mut ConsoleBufferInfo) == 0 {
+                return Err(last_error());
+            }
+
             let pos = Pos {
                 x: buffer_info.buffer_size.x as u16,
                 y: buffer_info.buffer_size.y as u16,
             };
 
-            return pos
+            return Ok(pos);
         }
     }
 }
+
+/// Returns the size of the visible console window in cells.
+// NOTE: The console does not report pixel sizes, those are always zero.
+pub fn window_size() -> Result<WindowSize> {
+    unsafe {
+        match window_size_tracked() {
+            Ok(window_size) => return Ok(window_size),
+            Err(error) if !supports_ansi => return Err(error),
+            Err(_) => {}
+        }
+
+        // NOTE: Output is not a console buffer (e.g. a pseudo console on the other end of a pipe),
+        //       ask the terminal itself. Fails with a timeout when nothing answers.
+        let prev = cursor_get()?;
+
+        cursor_set(u16::MAX, u16::MAX)?;
+
+        let mut size = cursor_get()?;
+        size.x = size.x.saturating_add(1);
+        size.y = size.y.saturating_add(1);
+
+        cursor_set(prev.x, prev.y)?;
+
+        return Ok(WindowSize { size, pixels: Pos::default() });
+    }
+}
+
+/// Size of the console window, without asking the terminal when the output is no console.
+pub(crate) fn window_size_tracked() -> Result<WindowSize> {
+    let mut buffer_info = ConsoleBufferInfo::default();
+    if unsafe { GetConsoleScreenBufferInfo(stdout, &mut buffer_info as *mut ConsoleBufferInfo) } == 0 {
+        return Err(last_error());
+    }
+
+    let window = buffer_info.window_coords;
+    let size = Pos {
+        x: (window.right - window.left + 1) as u16,
+        y: (window.bottom - window.top + 1) as u16,
+    };
+    return Ok(WindowSize { size, pixels: Pos::default() });
+}
+
+/// Input and output of a `Terminal`, which is always the console of the process here.
+pub(crate) struct Handle {
+    raw_mode: Option<RawMode>,
+}
+
+impl Handle {
+    pub(crate) fn stdio() -> Handle {
+        return Handle { raw_mode: None };
+    }
+
+    /// What `StylePolicy::Auto` comes down to, `None` when it follows the standard output.
+    pub(crate) fn style_auto(&self) -> Option<bool> {
+        return None;
+    }
+
+    pub(crate) fn write(&self, buffer: &[u8]) -> Result<()> {
+        print_buf(buffer, buffer.len())?;
+        return Ok(());
+    }
+
+    pub(crate) fn read_buf(&self, buffer: &mut [u8]) -> Result<usize> {
+        return read_buf(buffer);
+    }
+
+    pub(crate) fn read_event(&mut self, timeout: Option<Duration>) -> Result<Option<Event>> {
+        match timeout {
+            Some(timeout) => return poll_event(timeout),
+            None => return read_event().map(Some),
+        }
+    }
+
+    pub(crate) fn read_key_event(&mut self, timeout: Option<Duration>) -> Result<Option<KeyEvent>> {
+        match timeout {
+            Some(timeout) => return read_key_event_timeout(timeout),
+            None => return read_key_event().map(Some),
+        }
+    }
+
+    pub(crate) fn cursor_get(&mut self) -> Result<Pos> {
+        return cursor_get();
+    }
+
+    pub(crate) fn color_depth_query(&mut self) -> Result<ColorDepth> {
+        return color_depth_query();
+    }
+
+    pub(crate) fn window_size(&mut self) -> Result<WindowSize> {
+        return window_size();
+    }
+
+    pub(crate) fn raw_mode_enter(&mut self, config: RawConfig) -> Result<()> {
+        if self.raw_mode.is_none() {
+            let config = RawConfig { alternate_screen: false, ..config };
+            self.raw_mode = Some(RawMode::enter_with(config)?);
+        }
+
+        return Ok(());
+    }
+
+    pub(crate) fn raw_mode_leave(&mut self) -> Result<()> {
+        self.raw_mode = None;
+        return Ok(());
+    }
+}